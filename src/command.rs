@@ -39,6 +39,7 @@ const COMMANDS: &[Command] = &[
     Command::new("init", "Shell script to initialize the prompt", true),
     Command::new("guide", "Instructions for the shell", true),
     Command::new("please", "Add the init line to the shell rc file", true),
+    Command::new("daemon", "Watch recent repos to speed up the prompt", false),
     Command::new("debug", "Debugging info", false),
     Command::new("help", "Usage info", false).with_aliases(&["-h", "--help"]),
     Command::new("version", "Version info", false).with_aliases(&["-v", "--version"]),
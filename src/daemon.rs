@@ -0,0 +1,566 @@
+use crate::repo::{DiffStat, Dirtiness, parse_diff_stat};
+use crate::utils::runtime_path;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// The prompt falls back to checking by itself, so a busy daemon shouldn't hold it up
+const QUERY_TIMEOUT: Duration = Duration::from_millis(30);
+
+pub fn daemon_socket_path() -> Option<PathBuf> {
+    Some(runtime_path()?.join("daemon.sock"))
+}
+
+// The daemon only answers when it's sure that nothing changed since its last status walk.
+// A dirty answer carries the line counts when the daemon got to them.
+pub fn query_daemon(workdir: &Path) -> Option<(Dirtiness, Option<DiffStat>)> {
+    let mut stream = UnixStream::connect(daemon_socket_path()?).ok()?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT)).ok()?;
+
+    stream.write_all(workdir.as_os_str().as_bytes()).ok()?;
+    stream.write_all(b"\n").ok()?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).ok()?;
+
+    let mut fields = reply.split_whitespace();
+    match (fields.next()?, fields.next()) {
+        ("clean", _) => Some((Dirtiness::Clean, None)),
+        ("dirty", stat) => Some((Dirtiness::Dirty, stat.and_then(parse_diff_stat))),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn run_daemon() {
+    eprintln!("🆘 The daemon relies on inotify, which is only available on Linux");
+    std::process::exit(1);
+}
+
+#[cfg(target_os = "linux")]
+pub use watcher::run_daemon;
+
+#[cfg(target_os = "linux")]
+mod watcher {
+    use super::{QUERY_TIMEOUT, daemon_socket_path};
+    use crate::repo::{DiffStat, Dirtiness, global_excludes_file, repo_diff_stat, walk_dirtiness};
+    use gix::AttributeStack;
+    use gix::index::entry::Mode;
+    use gix::worktree::stack::state::ignore::Source;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+    use std::time::{Duration, Instant};
+    use std::{fs, mem, process, thread};
+
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+    const MAX_REPOS: usize = 16;
+    const MAX_WATCHES_PER_REPO: usize = 20_000;
+
+    // Lets a burst of changes, like a checkout or a build, settle before walking again
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    const READ_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    // Unlike the prompt, the daemon can afford to wait for a definite answer
+    const WALK_BUDGET: Duration = Duration::from_secs(60);
+
+    const WATCH_FLAGS: AddWatchFlags = AddWatchFlags::IN_MODIFY
+        .union(AddWatchFlags::IN_ATTRIB)
+        .union(AddWatchFlags::IN_CLOSE_WRITE)
+        .union(AddWatchFlags::IN_CREATE)
+        .union(AddWatchFlags::IN_DELETE)
+        .union(AddWatchFlags::IN_MOVED_FROM)
+        .union(AddWatchFlags::IN_MOVED_TO)
+        .union(AddWatchFlags::IN_DELETE_SELF)
+        .union(AddWatchFlags::IN_MOVE_SELF)
+        .union(AddWatchFlags::IN_ONLYDIR);
+
+    struct Watched {
+        // Bumped on every change, so a result is only valid for the generation it was walked at
+        generation: u64,
+        result: Option<(u64, Dirtiness, Option<DiffStat>)>,
+        is_watching: bool,
+        is_unwatchable: bool,
+        last_query: Instant,
+        watches: Vec<WatchDescriptor>,
+        // The index, the config and the exclude files decide which directories go unwatched
+        ignore_sources: Vec<PathBuf>,
+    }
+
+    #[derive(Clone)]
+    struct WatchedDir {
+        workdir: PathBuf,
+        dir: PathBuf,
+        // The directory of an exclude file outside the repo can hold anything else
+        is_repo_dir: bool,
+    }
+
+    struct State {
+        repos: HashMap<PathBuf, Watched>,
+        // Nested repos and a shared excludes file put a directory in more than one repo
+        watch_dirs: HashMap<WatchDescriptor, Vec<WatchedDir>>,
+        last_activity: Instant,
+    }
+
+    struct Shared {
+        state: Mutex<State>,
+        changed: Condvar,
+        inotify: Inotify,
+    }
+
+    impl Shared {
+        fn lock(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    pub fn run_daemon() {
+        let pkg = env!("CARGO_PKG_NAME");
+
+        let Some(socket_path) = daemon_socket_path() else {
+            eprintln!("🆘 Could not determine where to put the daemon socket");
+            process::exit(1);
+        };
+
+        if UnixStream::connect(&socket_path).is_ok() {
+            println!("✅ The {pkg} daemon is already running");
+            return;
+        }
+
+        let listener = socket_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| match fs::remove_file(&socket_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => UnixListener::bind(&socket_path),
+            });
+
+        let (listener, inotify) = match (listener, Inotify::init(InitFlags::IN_CLOEXEC)) {
+            (Ok(l), Ok(i)) => (l, i),
+            (Err(e), _) => {
+                eprintln!("🆘 Could not listen on {}: {e}", socket_path.display());
+                process::exit(1);
+            }
+            (_, Err(e)) => {
+                eprintln!("🆘 Could not start watching for changes: {e}");
+                process::exit(1);
+            }
+        };
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                repos: HashMap::new(),
+                watch_dirs: HashMap::new(),
+                last_activity: Instant::now(),
+            }),
+            changed: Condvar::new(),
+            inotify,
+        });
+
+        thread::spawn({
+            let shared = shared.clone();
+            move || watch_events(&shared)
+        });
+
+        thread::spawn({
+            let shared = shared.clone();
+            move || walk_changed_repos(&shared)
+        });
+
+        thread::spawn({
+            let shared = shared.clone();
+            move || exit_when_idle(&shared, &socket_path)
+        });
+
+        for stream in listener.incoming().flatten() {
+            answer_query(&shared, stream);
+        }
+    }
+
+    fn answer_query(shared: &Shared, stream: UnixStream) {
+        // Queries are answered one at a time, and the prompt won't wait any longer anyway
+        if stream.set_read_timeout(Some(QUERY_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(QUERY_TIMEOUT)).is_err()
+        {
+            return;
+        }
+
+        let mut line = Vec::new();
+        if BufReader::new(&stream)
+            .read_until(b'\n', &mut line)
+            .is_err()
+        {
+            return;
+        }
+
+        let path = Path::new(OsStr::from_bytes(line.trim_ascii_end()));
+        let Ok(workdir) = path.canonicalize() else {
+            return;
+        };
+
+        let reply = {
+            let mut state = shared.lock();
+            state.last_activity = Instant::now();
+
+            let repo = state.repos.entry(workdir).or_insert_with(|| Watched {
+                generation: 0,
+                result: None,
+                is_watching: false,
+                is_unwatchable: false,
+                last_query: Instant::now(),
+                watches: Vec::new(),
+                ignore_sources: Vec::new(),
+            });
+            repo.last_query = Instant::now();
+
+            let reply = match repo.result {
+                Some((generation, Dirtiness::Clean, _)) if generation == repo.generation => {
+                    "clean".to_string()
+                }
+                Some((generation, Dirtiness::Dirty, stat)) if generation == repo.generation => {
+                    match stat {
+                        Some(s) => format!("dirty {},{}", s.insertions, s.deletions),
+                        None => "dirty".to_string(),
+                    }
+                }
+                _ => "pending".to_string(),
+            };
+
+            evict_old_repos(shared, &mut state);
+            reply
+        };
+
+        shared.changed.notify_all();
+        _ = (&stream).write_all(format!("{reply}\n").as_bytes());
+    }
+
+    fn evict_old_repos(shared: &Shared, state: &mut State) {
+        while state.repos.len() > MAX_REPOS {
+            let Some(oldest) = state
+                .repos
+                .iter()
+                .min_by_key(|(_, r)| r.last_query)
+                .map(|(p, _)| p.clone())
+            else {
+                return;
+            };
+
+            if let Some(repo) = state.repos.remove(&oldest) {
+                remove_watches(shared, state, &oldest, repo.watches);
+            }
+        }
+    }
+
+    fn watch_events(shared: &Shared) {
+        loop {
+            let Ok(events) = shared.inotify.read_events() else {
+                // Whatever broke the read is unlikely to clear up within a busy loop
+                thread::sleep(READ_RETRY_DELAY);
+                continue;
+            };
+
+            let mut state = shared.lock();
+            for event in events {
+                if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    for repo in state.repos.values_mut() {
+                        repo.generation += 1;
+                    }
+                    continue;
+                }
+
+                let Some(dirs) = state.watch_dirs.get(&event.wd).cloned() else {
+                    continue;
+                };
+
+                let new_dir = event.mask.contains(AddWatchFlags::IN_ISDIR)
+                    && event
+                        .mask
+                        .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO);
+
+                for WatchedDir {
+                    workdir,
+                    dir,
+                    is_repo_dir,
+                } in dirs
+                {
+                    let Some(repo) = state.repos.get_mut(&workdir) else {
+                        continue;
+                    };
+                    let path = event.name.as_ref().map(|name| dir.join(name));
+                    let changes_ignores = path.as_ref().is_some_and(|p| {
+                        repo.ignore_sources.contains(p)
+                            || is_repo_dir && p.file_name() == Some(OsStr::new(".gitignore"))
+                    });
+                    if !is_repo_dir && !changes_ignores {
+                        continue;
+                    }
+                    repo.generation += 1;
+
+                    // A directory that was ignored may hold tracked files now, or the other
+                    // way around, so the watches are set up again before the next walk
+                    if changes_ignores {
+                        let watches = mem::take(&mut repo.watches);
+                        repo.is_watching = false;
+                        remove_watches(shared, &mut state, &workdir, watches);
+                    } else if new_dir
+                        && repo.is_watching
+                        && let Some(path) = path
+                    {
+                        let watches = add_watches(shared, &mut state, &workdir, &path);
+                        if let Some(repo) = state.repos.get_mut(&workdir) {
+                            repo.watches.extend(watches);
+                        }
+                    }
+                }
+            }
+
+            drop(state);
+            shared.changed.notify_all();
+        }
+    }
+
+    fn walk_changed_repos(shared: &Shared) {
+        loop {
+            let workdir = {
+                let mut state = shared.lock();
+                loop {
+                    let stale = state.repos.iter().find(|(_, r)| {
+                        !r.is_unwatchable && r.result.is_none_or(|(g, _, _)| g != r.generation)
+                    });
+                    if let Some((workdir, _)) = stale {
+                        break workdir.clone();
+                    }
+                    state = shared
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            };
+
+            thread::sleep(DEBOUNCE);
+
+            let generation = {
+                let mut state = shared.lock();
+                let Some(repo) = state.repos.get(&workdir) else {
+                    continue;
+                };
+
+                // Watch before walking, so that changes during the walk aren't missed
+                if !repo.is_watching {
+                    let watches = watch_repo(shared, &mut state, &workdir);
+                    let Some(repo) = state.repos.get_mut(&workdir) else {
+                        continue;
+                    };
+                    match watches {
+                        Some((watches, ignore_sources)) => {
+                            repo.watches = watches;
+                            repo.ignore_sources = ignore_sources;
+                            repo.is_watching = true;
+                        }
+                        None => {
+                            repo.is_unwatchable = true;
+                            continue;
+                        }
+                    }
+                }
+
+                state.repos.get(&workdir).map(|r| r.generation)
+            };
+
+            // The prompt shows the line counts with the dirty state, so they're counted here
+            // rather than making every prompt do it
+            let (dirtiness, diff_stat) = match gix::open(&workdir) {
+                Ok(r) => match walk_dirtiness(&r, WALK_BUDGET) {
+                    Dirtiness::Dirty => (Dirtiness::Dirty, repo_diff_stat(&r, WALK_BUDGET)),
+                    dirtiness => (dirtiness, None),
+                },
+                Err(_) => (Dirtiness::Unknown, None),
+            };
+
+            let mut state = shared.lock();
+            if let Some(repo) = state.repos.get_mut(&workdir)
+                && let Some(generation) = generation
+            {
+                match dirtiness {
+                    Dirtiness::Unknown => repo.is_unwatchable = true,
+                    _ => repo.result = Some((generation, dirtiness, diff_stat)),
+                }
+            }
+        }
+    }
+
+    fn watch_repo(
+        shared: &Shared,
+        state: &mut State,
+        workdir: &Path,
+    ) -> Option<(Vec<WatchDescriptor>, Vec<PathBuf>)> {
+        let repo = gix::open(workdir).ok()?;
+
+        let mut watches = add_watches(shared, state, workdir, workdir);
+
+        // HEAD, the index and refs decide what the worktree is compared against
+        let git_dir = repo.git_dir().to_path_buf();
+        let refs = repo.common_dir().join("refs");
+        for dir in [git_dir, refs] {
+            watches.extend(add_watches(shared, state, workdir, &dir));
+        }
+
+        // Only the directories of the exclude files, since the global one can sit in $HOME.
+        // The config says where that one is.
+        let excludes = [
+            Some(repo.common_dir().join("config")),
+            Some(repo.common_dir().join("info").join("exclude")),
+            global_excludes_file(&repo),
+        ];
+        for dir in excludes.iter().flatten().filter_map(|p| p.parent()) {
+            watches.extend(add_watch(shared, state, workdir, dir, false));
+        }
+
+        if watches.len() > MAX_WATCHES_PER_REPO {
+            remove_watches(shared, state, workdir, watches);
+            return None;
+        }
+
+        let mut ignore_sources = vec![repo.index_path()];
+        ignore_sources.extend(excludes.into_iter().flatten());
+        Some((watches, ignore_sources))
+    }
+
+    fn add_watch(
+        shared: &Shared,
+        state: &mut State,
+        workdir: &Path,
+        dir: &Path,
+        is_repo_dir: bool,
+    ) -> Option<WatchDescriptor> {
+        let wd = shared.inotify.add_watch(dir, WATCH_FLAGS).ok()?;
+        let dirs = state.watch_dirs.entry(wd).or_default();
+        match dirs.iter_mut().find(|d| d.workdir == workdir) {
+            Some(watched) => watched.is_repo_dir |= is_repo_dir,
+            None => dirs.push(WatchedDir {
+                workdir: workdir.to_path_buf(),
+                dir: dir.to_path_buf(),
+                is_repo_dir,
+            }),
+        }
+        Some(wd)
+    }
+
+    // A directory stays watched as long as any other repo still needs it
+    fn remove_watches(
+        shared: &Shared,
+        state: &mut State,
+        workdir: &Path,
+        watches: Vec<WatchDescriptor>,
+    ) {
+        for wd in watches {
+            let Some(dirs) = state.watch_dirs.get_mut(&wd) else {
+                continue;
+            };
+            dirs.retain(|d| d.workdir != workdir);
+            if dirs.is_empty() {
+                state.watch_dirs.remove(&wd);
+                _ = shared.inotify.rm_watch(wd);
+            }
+        }
+    }
+
+    fn add_watches(
+        shared: &Shared,
+        state: &mut State,
+        workdir: &Path,
+        dir: &Path,
+    ) -> Vec<WatchDescriptor> {
+        let mut watches = Vec::new();
+        let mut pending = vec![dir.to_path_buf()];
+
+        let repo = gix::open(workdir).ok();
+        let index = repo.as_ref().and_then(|r| r.index_or_empty().ok());
+        let mut excludes = repo.as_ref().zip(index.as_ref()).and_then(|(r, i)| {
+            r.excludes(i, None, Source::WorktreeThenIdMappingIfNotSkipped)
+                .ok()
+        });
+
+        while let Some(dir) = pending.pop() {
+            if watches.len() > MAX_WATCHES_PER_REPO {
+                break;
+            }
+
+            if let (Some(excludes), Some(index)) = (&mut excludes, &index)
+                && is_ignored_dir(excludes, index, workdir, &dir)
+            {
+                continue;
+            }
+
+            let Some(wd) = add_watch(shared, state, workdir, &dir, true) else {
+                continue;
+            };
+            watches.push(wd);
+
+            // The git dir is watched separately, without its object store
+            let is_git_dir = dir.file_name() == Some(OsStr::new(".git"))
+                || dir.join("HEAD").is_file() && dir.join("objects").is_dir();
+            if is_git_dir {
+                continue;
+            }
+
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+                if is_dir && entry.file_name() != ".git" {
+                    pending.push(entry.path());
+                }
+            }
+        }
+
+        watches
+    }
+
+    // Build output and dependencies change all the time without ever making the repo dirty,
+    // unless something in them is tracked after all
+    fn is_ignored_dir(
+        excludes: &mut AttributeStack<'_>,
+        index: &gix::index::State,
+        workdir: &Path,
+        dir: &Path,
+    ) -> bool {
+        let Ok(rel) = dir.strip_prefix(workdir) else {
+            return false;
+        };
+        if rel.as_os_str().is_empty() || rel.starts_with(".git") {
+            return false;
+        }
+        let Some(prefix) = rel.to_str().map(|r| format!("{r}/")) else {
+            return false;
+        };
+
+        let has_tracked = index
+            .prefixed_entries(prefix.as_str().into())
+            .is_some_and(|entries| !entries.is_empty());
+        !has_tracked
+            && excludes
+                .at_path(rel, Some(Mode::DIR))
+                .is_ok_and(|p| p.is_excluded())
+    }
+
+    fn exit_when_idle(shared: &Shared, socket_path: &Path) {
+        loop {
+            thread::sleep(Duration::from_secs(10));
+            if shared.lock().last_activity.elapsed() > IDLE_TIMEOUT {
+                _ = fs::remove_file(socket_path);
+                process::exit(0);
+            }
+        }
+    }
+}
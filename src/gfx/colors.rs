@@ -51,6 +51,31 @@ pub fn prompt_256color_bg_colors(term_bg: Srgb) -> (u8, u8) {
     )
 }
 
+// A peach that reads as a warning on the prompt backgrounds without shouting
+pub fn prompt_oklab_warning_color(term_bg: Srgb) -> Srgb {
+    let bg: Oklab = term_bg.into();
+
+    let peach = if bg.is_dark() {
+        Oklab::from((250, 179, 135))
+    } else {
+        Oklab::from((254, 100, 11))
+    };
+
+    // Keep the hue, but never drift towards the background's own luminance
+    let l = if bg.is_dark() {
+        peach.l.max(bg.l + 0.45)
+    } else {
+        peach.l.min(bg.l - 0.3)
+    };
+
+    Oklab { l, ..peach }.into()
+}
+
+pub fn prompt_256color_warning_color(term_bg: Srgb) -> u8 {
+    let bg: Oklab = term_bg.into();
+    if bg.is_dark() { 216 } else { 202 }
+}
+
 impl Oklab {
     pub fn lerp(&self, other: &Oklab, t: f64) -> Self {
         Self {
@@ -199,6 +224,15 @@ impl From<Oklab> for Rgb24 {
     }
 }
 
+#[test]
+fn test_warning_color_contrast() {
+    for bg in [(0, 0, 0), (30, 30, 46), (239, 241, 245), (255, 255, 255)] {
+        let bg = Srgb::from(bg);
+        let warning = Oklab::from(Rgb24::from(prompt_oklab_warning_color(bg)));
+        assert!((warning.l - Oklab::from(bg).l).abs() > 0.25);
+    }
+}
+
 #[test]
 fn test_linear_roundtrip() {
     for x in 0..=255 {
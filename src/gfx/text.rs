@@ -104,15 +104,23 @@ pub fn green(s: &str) -> String {
     format!("\x1b[32m{s}\x1b[0m")
 }
 
+// An OSC 8 hyperlink, or the end of one when the url is empty. It ends with BEL rather than
+// ESC \, since bash would read that backslash and its own \] wrapper as an escaped backslash.
+fn encode_link(url: &str, wrappers: Option<NonPrintingWrappers>) -> String {
+    wrap_sgr(&format!("\x1b]8;;{url}\x07"), wrappers)
+}
+
 #[derive(Clone)]
 struct Fragment {
     text: String,
     style: Style,
+    link: Option<String>,
 }
 
 #[derive(Default)]
 pub struct TextBuilder {
     style: Style,
+    link: Option<String>,
     fragments: Vec<Fragment>,
 }
 
@@ -136,10 +144,16 @@ impl TextBuilder {
         self
     }
 
+    pub fn link(&mut self, url: Option<&str>) -> &mut Self {
+        self.link = url.map(String::from);
+        self
+    }
+
     pub fn text(&mut self, text: &str) -> &mut Self {
         self.fragments.push(Fragment {
             text: text.into(),
             style: self.style,
+            link: self.link.clone(),
         });
         self
     }
@@ -166,15 +180,24 @@ impl StyledText {
         }
 
         let mut style: Option<&Style> = None;
+        let mut link: Option<&str> = None;
 
         for frag in &self.fragments {
             if let Some(s) = encode_style(style, &frag.style, wrappers) {
                 result.push_str(&s);
             }
+            if frag.link.as_deref() != link {
+                link = frag.link.as_deref();
+                result.push_str(&encode_link(link.unwrap_or_default(), wrappers));
+            }
             result.push_str(&frag.text);
             style = Some(&frag.style);
         }
 
+        if link.is_some() {
+            result.push_str(&encode_link("", wrappers));
+        }
+
         let reset = wrap_sgr("\x1b[0m", wrappers);
         result.push_str(&reset);
         result
@@ -1,7 +1,18 @@
+use crate::daemon::daemon_socket_path;
 use crate::gfx::{Rgb24, term_background};
-use crate::repo::{current_repo, is_repo_dirty, repo_head_description, repo_state};
+use crate::hg::{check_hg_dirty, current_hg_repo, hg_bookmark, hg_branch, hg_repo_state};
+use crate::jj::{current_jj_workspace, jj_working_copy};
+use crate::repo::{
+    FsMonitor, cached_has_untracked_files, check_repo_dirty, configured_fsmonitor, current_repo,
+    default_branch_divergence, dirty_check_config, fetch_head_age, head_commit_age,
+    is_branch_checked_out_elsewhere, is_head_detached, is_on_protected_branch, is_repo_trusted,
+    is_untracked_home_dir, origin_web, pending_squash_count, repo_discovery_outcome,
+    repo_git_dir_marker, repo_head_description, repo_identity_problem, repo_linked_worktree,
+    repo_shape, repo_stale_locks, repo_stash_count, repo_state, repo_superproject,
+};
 use crate::utils::{
-    current_path, displayed_path, home_path, supports_256color, supports_truecolor,
+    current_path, displayed_path, env_git_dir, env_git_work_tree, home_path, short_age,
+    supports_256color, supports_truecolor,
 };
 use std::path::Path;
 
@@ -13,8 +24,12 @@ pub fn print_debug() {
         path.map(|p| p.display().to_string()).unwrap_or_default()
     }
 
+    println!("GIT_DIR: {}", fmt_opt(env_git_dir()));
+    println!("GIT_WORK_TREE: {}", fmt_opt(env_git_work_tree()));
+
     let repo = current_repo();
     println!("Is in repo: {}", repo.is_some());
+    println!("Repo discovery: {}", fmt_opt(repo_discovery_outcome()));
 
     let path = current_path();
     println!("Current path: {}", fmt_opt_path(path.as_deref()));
@@ -25,18 +40,149 @@ pub fn print_debug() {
     let workdir_path = repo.as_ref().and_then(|r| r.workdir());
     println!("Working directory: {}", fmt_opt_path(workdir_path));
 
-    let display_path = displayed_path(workdir_path);
+    let superproject = repo.as_ref().and_then(repo_superproject);
+    let superproject_workdir = superproject.as_ref().map(|s| s.workdir.as_path());
+    println!("Superproject: {}", fmt_opt_path(superproject_workdir));
+
+    let display_root = repo.as_ref().map(|r| workdir_path.unwrap_or(r.git_dir()));
+    let display_path = displayed_path(display_root, superproject_workdir);
     println!("Path to display: {}", fmt_opt(display_path));
 
     if let Some(r) = &repo {
+        let untracked_home_dir = is_untracked_home_dir(r);
+        println!("Untracked directory in a home repo: {untracked_home_dir}");
+
+        let trusted = is_repo_trusted(r);
+        println!("Repo trusted: {trusted}");
+        if !trusted {
+            let dir = r.workdir().unwrap_or(r.git_dir()).display();
+            println!("  The repo is owned by another user, so git refuses to work in it.");
+            println!("  If you trust it, run: git config --global --add safe.directory {dir}");
+        }
+
+        match origin_web(r) {
+            Some(w) => {
+                println!("Repo forge: {:?}", w.forge);
+                println!("Repo web URL: {}", w.url);
+            }
+            None => println!("Repo web URL: "),
+        }
+
         println!("Repo state: {}", repo_state(r).unwrap_or("Clean"));
+        for lock in repo_stale_locks(r) {
+            let (path, age) = (lock.path.display(), short_age(lock.age));
+            println!("Stale lock: {path}, left {age} ago");
+        }
 
         let head = repo_head_description(r);
         println!("Repo head: {}", fmt_opt(head));
+        println!("Repo head detached: {}", is_head_detached(r));
+        println!("Repo on protected branch: {}", is_on_protected_branch(r));
+
+        let age = head_commit_age(r).map(short_age);
+        println!("Repo head commit age: {}", fmt_opt(age));
+        let fetch_age = fetch_head_age(r).map(short_age);
+        println!("FETCH_HEAD age: {}", fmt_opt(fetch_age));
+
+        let git_dir_marker = repo_git_dir_marker(r);
+        println!("Repo git dir marker: {}", fmt_opt(git_dir_marker));
+
+        if let Some(s) = &superproject {
+            let differs = s.records_other_commit;
+            println!("Superproject records a different commit: {differs}");
+        }
+
+        let elsewhere = is_branch_checked_out_elsewhere(r);
+        println!("Branch checked out in another worktree: {elsewhere}");
+
+        match repo_linked_worktree(r) {
+            Some(w) => {
+                println!("Linked worktree: {}", w.name);
+                println!("Worktree locked: {}", w.is_locked);
+                println!("Worktree prunable: {}", w.is_prunable);
+            }
+            None => println!("Linked worktree: "),
+        }
 
-        println!("Repo dirty: {}", is_repo_dirty(r));
+        let fsmonitor = match configured_fsmonitor(r) {
+            Some(FsMonitor::Daemon(socket)) => format!("built-in daemon at {}", socket.display()),
+            Some(FsMonitor::Hook { command, .. }) => format!("hook {}", command.display()),
+            None => String::new(),
+        };
+        println!("Repo fsmonitor: {fsmonitor}");
+
+        let untracked_cache = match cached_has_untracked_files(r) {
+            Some(true) => "has untracked files",
+            Some(false) => "no untracked files",
+            None => "",
+        };
+        println!("Repo untracked cache: {untracked_cache}");
+
+        let config = dirty_check_config(r);
+        println!("Show dirty state: {}", config.show_dirty_state);
+        println!("Show untracked files: {}", config.show_untracked_files);
+        println!(
+            "Ignore submodules: {}",
+            fmt_opt(config.ignore_submodules.map(|i| format!("{i:?}")))
+        );
+
+        match default_branch_divergence(r) {
+            Some(d) => {
+                let more = |truncated| if truncated { " or more" } else { "" };
+                let ahead = format!("{}{}", d.ahead, more(d.is_ahead_truncated));
+                let behind = format!("{}{}", d.behind, more(d.is_behind_truncated));
+                println!("Divergence from {}: {ahead} ahead, {behind} behind", d.base);
+            }
+            None => println!("Divergence from the default branch: "),
+        }
+
+        let dirty = check_repo_dirty(r);
+        println!("Repo dirty: {:?}", dirty.dirtiness);
+        println!("Repo status walk took: {:?}", dirty.elapsed);
+        println!("Repo status source: {:?}", dirty.source);
+        match dirty.diff_stat {
+            Some(s) => println!("Repo diff stat: +{} -{}", s.insertions, s.deletions),
+            None => println!("Repo diff stat: "),
+        }
+        let squashes = pending_squash_count(r);
+        println!("Pending fixup/squash/WIP commits: {}", fmt_opt(squashes));
+        let identity = repo_identity_problem(r).map(|p| format!("{p:?}"));
+        println!("Commit identity problem: {}", fmt_opt(identity));
+        println!("Repo stashes: {}", repo_stash_count(r));
+
+        let shape = repo_shape(r);
+        println!(
+            "Shallow boundary commits: {}",
+            fmt_opt(shape.shallow_commits)
+        );
+        println!("Sparse checkout: {}", fmt_opt(shape.sparse_checkout));
+        let promisors = shape.promisor_remotes.join(", ");
+        println!("Partial clone promisor remotes: {promisors}");
     }
 
+    let hg = current_hg_repo(None);
+    println!("Is in hg repo: {}", hg.is_some());
+    if let Some(h) = &hg {
+        println!("Hg root: {}", h.root.display());
+        println!("Hg branch: {}", hg_branch(h));
+        println!("Hg bookmark: {}", fmt_opt(hg_bookmark(h)));
+        println!("Hg state: {}", hg_repo_state(h).unwrap_or("Clean"));
+        println!("Hg dirty: {:?}", check_hg_dirty(h));
+    }
+
+    let jj = current_jj_workspace(None);
+    println!("Jj workspace: {}", fmt_opt_path(jj.as_deref()));
+    if let Some(wc) = jj.as_deref().and_then(jj_working_copy) {
+        let (prefix, rest) = &wc.change_id;
+        println!("Jj change: {prefix}[{rest}]");
+        println!("Jj bookmarks: {}", wc.bookmarks.join(", "));
+        println!("Jj empty: {}", wc.is_empty);
+        println!("Jj conflict: {}", wc.has_conflict);
+    }
+
+    let daemon_socket = daemon_socket_path();
+    println!("Daemon socket: {}", fmt_opt_path(daemon_socket.as_deref()));
+
     let bg = term_background()
         .map(Rgb24::from)
         .map(|(r, g, b)| format!("{r}, {g}, {b}"));
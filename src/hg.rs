@@ -0,0 +1,188 @@
+use crate::repo::{DIRTY_CHECK_BUDGET, Dirtiness, find_upwards};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const NULL_NODE: [u8; 20] = [0; 20];
+
+pub struct HgRepo {
+    pub root: PathBuf,
+    dot_hg: PathBuf,
+}
+
+pub fn current_hg_repo(outer: Option<&Path>) -> Option<HgRepo> {
+    let root = find_upwards(".hg", outer)?;
+    Some(HgRepo {
+        dot_hg: root.join(".hg"),
+        root,
+    })
+}
+
+pub fn hg_repo_state(repo: &HgRepo) -> Option<&str> {
+    let exists = |name: &str| repo.dot_hg.join(name).exists();
+
+    if exists("rebasestate") {
+        Some("Rebasing")
+    } else if exists("histedit-state") {
+        Some("Histediting")
+    } else if exists("graftstate") {
+        Some("🍒")
+    } else if exists("shelvedstate") {
+        Some("Unshelving")
+    } else if exists("updatestate") {
+        Some("Updating")
+    } else if exists("bisect.state") {
+        Some("Bisecting")
+    } else if exists("merge/state2") || exists("merge/state") || has_second_parent(repo) {
+        Some("Merging")
+    } else {
+        None
+    }
+}
+
+// Like hg's own prompt extensions, the active bookmark wins over the branch
+pub fn hg_head_description(repo: &HgRepo) -> Option<String> {
+    hg_bookmark(repo).or_else(|| Some(hg_branch(repo)))
+}
+
+pub fn hg_bookmark(repo: &HgRepo) -> Option<String> {
+    let bookmark = fs::read_to_string(repo.dot_hg.join("bookmarks.current")).ok()?;
+    Some(bookmark.trim().to_string()).filter(|b| !b.is_empty())
+}
+
+pub fn hg_branch(repo: &HgRepo) -> String {
+    fs::read_to_string(repo.dot_hg.join("branch"))
+        .ok()
+        .map(|b| b.trim().to_string())
+        .filter(|b| !b.is_empty())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+// Only tracked files are compared, since finding untracked ones would mean matching .hgignore
+pub fn check_hg_dirty(repo: &HgRepo) -> Dirtiness {
+    // The newer dirstate format is a tree behind a docket, which isn't read here
+    if is_dirstate_v2(repo) {
+        return Dirtiness::Unknown;
+    }
+
+    match fs::read(repo.dot_hg.join("dirstate")) {
+        Ok(dirstate) => dirstate_dirtiness(&repo.root, &dirstate, DIRTY_CHECK_BUDGET)
+            .unwrap_or(Dirtiness::Unknown),
+        // A fresh clone with nothing checked out has no dirstate yet
+        Err(_) => Dirtiness::Clean,
+    }
+}
+
+fn is_dirstate_v2(repo: &HgRepo) -> bool {
+    let requires = fs::read_to_string(repo.dot_hg.join("requires")).unwrap_or_default();
+    requires.lines().any(|l| l == "dirstate-v2")
+}
+
+// A version 2 docket starts with a marker line and keeps each parent in 32 bytes
+fn has_second_parent(repo: &HgRepo) -> bool {
+    let p2 = if is_dirstate_v2(repo) { 44..76 } else { 20..40 };
+    fs::read(repo.dot_hg.join("dirstate"))
+        .ok()
+        .and_then(|d| d.get(p2).map(|p2| p2.iter().any(|&b| b != 0)))
+        .unwrap_or(false)
+}
+
+struct DirstateEntry<'a> {
+    state: u8,
+    mode: i32,
+    size: i32,
+    mtime: i32,
+    path: &'a [u8],
+}
+
+// Version 1 is both parents followed by fixed size big endian records, each with its path
+fn parse_dirstate(dirstate: &[u8]) -> Option<Vec<DirstateEntry<'_>>> {
+    let mut rest = dirstate.get(40..)?;
+    let mut entries = Vec::new();
+
+    while !rest.is_empty() {
+        let int = |at: usize| Some(i32::from_be_bytes(rest.get(at..at + 4)?.try_into().ok()?));
+        let len = usize::try_from(int(13)?).ok()?;
+        let name = rest.get(17..17 + len)?;
+
+        entries.push(DirstateEntry {
+            state: rest[0],
+            mode: int(1)?,
+            size: int(5)?,
+            mtime: int(9)?,
+            // A copy source follows the path after a NUL
+            path: name.split(|&b| b == 0).next()?,
+        });
+        rest = &rest[17 + len..];
+    }
+
+    Some(entries)
+}
+
+fn dirstate_dirtiness(root: &Path, dirstate: &[u8], budget: Duration) -> Option<Dirtiness> {
+    if dirstate.get(20..40)? != NULL_NODE {
+        return Some(Dirtiness::Dirty);
+    }
+
+    let start = Instant::now();
+    let mut unsure = false;
+    for (i, entry) in parse_dirstate(dirstate)?.iter().enumerate() {
+        // Like the git walk, give up rather than hold the prompt up
+        if i % 256 == 0 && start.elapsed() > budget {
+            return Some(Dirtiness::Unknown);
+        }
+
+        // Anything but "normal" is an add, a removal or a merge waiting to be committed
+        if entry.state != b'n' || entry.size == -2 {
+            return Some(Dirtiness::Dirty);
+        }
+
+        let path = root.join(std::str::from_utf8(entry.path).ok()?);
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            return Some(Dirtiness::Dirty);
+        };
+
+        let size = (meta.size() & 0x7fff_ffff) as i32;
+        let mtime = (meta.mtime() & 0x7fff_ffff) as i32;
+        let exec_changed = (entry.mode as u32 ^ meta.mode()) & 0o100 != 0;
+
+        if entry.size >= 0 && (size != entry.size || exec_changed) {
+            return Some(Dirtiness::Dirty);
+        }
+        // hg would compare contents here, which needs the revlogs
+        if entry.size < 0 || mtime != entry.mtime {
+            unsure = true;
+        }
+    }
+
+    Some(if unsure {
+        Dirtiness::Unknown
+    } else {
+        Dirtiness::Clean
+    })
+}
+
+#[test]
+fn test_parse_dirstate() {
+    let mut dirstate = vec![7; 20];
+    dirstate.extend_from_slice(&NULL_NODE);
+    for (state, path) in [(b'n', &b"src/lib.rs"[..]), (b'a', b"new.rs\0old.rs")] {
+        dirstate.push(state);
+        for int in [0o100644, 12, 1_700_000_000, path.len() as i32] {
+            dirstate.extend_from_slice(&int.to_be_bytes());
+        }
+        dirstate.extend_from_slice(path);
+    }
+
+    let entries = parse_dirstate(&dirstate).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].state, b'n');
+    assert_eq!(entries[0].path, b"src/lib.rs");
+    assert_eq!(entries[0].size, 12);
+    assert_eq!(entries[0].mtime, 1_700_000_000);
+    assert_eq!(entries[1].state, b'a');
+    assert_eq!(entries[1].path, b"new.rs");
+
+    assert!(parse_dirstate(&dirstate[..dirstate.len() - 1]).is_none());
+}
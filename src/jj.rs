@@ -0,0 +1,72 @@
+use crate::repo::find_upwards;
+use crate::utils::output_within;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+// jj loads its whole operation log view on startup, which is fast but not free
+const JJ_TIMEOUT: Duration = Duration::from_millis(300);
+
+const TEMPLATE: &str = r#"change_id.shortest(8).prefix() ++ "\n"
+    ++ change_id.shortest(8).rest() ++ "\n"
+    ++ local_bookmarks.map(|b| b.name()).join(" ") ++ "\n"
+    ++ if(empty, "empty") ++ "\n"
+    ++ if(conflict, "conflict")"#;
+
+pub struct JjWorkingCopy {
+    // The shortest unique prefix, and the rest of the usual 8 characters
+    pub change_id: (String, String),
+    pub bookmarks: Vec<String>,
+    pub is_empty: bool,
+    pub has_conflict: bool,
+}
+
+pub fn current_jj_workspace(outer: Option<&Path>) -> Option<PathBuf> {
+    find_upwards(".jj", outer)
+}
+
+pub fn jj_working_copy(workspace: &Path) -> Option<JjWorkingCopy> {
+    // Snapshotting would record a new operation whenever a file changed between prompts
+    let mut command = Command::new("jj");
+    command
+        .args(["--ignore-working-copy", "--no-pager", "--color", "never"])
+        .arg("-R")
+        .arg(workspace)
+        .args(["log", "--no-graph", "-r", "@", "-T", TEMPLATE]);
+
+    let output = String::from_utf8(output_within(&mut command, JJ_TIMEOUT)?).ok()?;
+    parse_working_copy(&output)
+}
+
+fn parse_working_copy(output: &str) -> Option<JjWorkingCopy> {
+    let mut lines = output.lines();
+    let prefix = lines.next()?.to_string();
+    let rest = lines.next()?.to_string();
+    if prefix.is_empty() {
+        return None;
+    }
+
+    let bookmarks = lines.next()?.split_whitespace().map(String::from).collect();
+    Some(JjWorkingCopy {
+        change_id: (prefix, rest),
+        bookmarks,
+        is_empty: lines.next() == Some("empty"),
+        has_conflict: lines.next() == Some("conflict"),
+    })
+}
+
+#[test]
+fn test_parse_working_copy() {
+    let wc = parse_working_copy("kx\nyzmpqr\nmain feature\nempty\n").unwrap();
+    assert_eq!(wc.change_id, ("kx".to_string(), "yzmpqr".to_string()));
+    assert_eq!(wc.bookmarks, ["main", "feature"]);
+    assert!(wc.is_empty);
+    assert!(!wc.has_conflict);
+
+    let wc = parse_working_copy("q\nlmnopqr\n\n\nconflict").unwrap();
+    assert!(wc.bookmarks.is_empty());
+    assert!(!wc.is_empty);
+    assert!(wc.has_conflict);
+
+    assert!(parse_working_copy("").is_none());
+}
@@ -1,7 +1,10 @@
 mod command;
+mod daemon;
 mod gfx;
 mod help;
+mod hg;
 mod init;
+mod jj;
 mod prompt;
 mod repo;
 mod utils;
@@ -10,6 +13,7 @@ use crate::command::{
     Invocation::{Bare, Shell, Unknown},
     parse_args, print_usage,
 };
+use crate::daemon::run_daemon;
 use crate::help::{please, print_debug, print_guide};
 use crate::init::print_init;
 use crate::prompt::print_prompt;
@@ -28,6 +32,7 @@ fn main() {
             "help" => print_usage(),
             "version" => print_version(),
             "debug" => print_debug(),
+            "daemon" => run_daemon(),
             _ => unknown_command(name),
         },
         Unknown { name } => unknown_command(&name),
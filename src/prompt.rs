@@ -1,9 +1,21 @@
 use crate::gfx::{
     NonPrintingWrappers, Oklab, Srgb, TermColor, TextBuilder, prompt_256color_bg_colors,
-    prompt_oklab_bg_colors, term_background,
+    prompt_256color_warning_color, prompt_oklab_bg_colors, prompt_oklab_warning_color,
+    term_background,
 };
-use crate::repo::{current_repo, is_repo_dirty, repo_head_description, repo_state};
-use crate::utils::{displayed_path, supports_256color, supports_truecolor};
+use crate::hg::{check_hg_dirty, current_hg_repo, hg_head_description, hg_repo_state};
+use crate::jj::{current_jj_workspace, jj_working_copy};
+use crate::repo::{
+    DiffStat, Dirtiness, Superproject, check_repo_dirty, current_repo, default_branch_divergence,
+    head_commit_age, is_branch_checked_out_elsewhere, is_fetch_stale, is_head_detached,
+    is_on_protected_branch, is_repo_trusted, is_untracked_home_dir, origin_web,
+    pending_squash_count, repo_git_dir_marker, repo_head_description, repo_identity_problem,
+    repo_linked_worktree, repo_shape, repo_stale_locks, repo_stash_count, repo_state,
+    repo_superproject,
+};
+use crate::utils::{displayed_path, short_age, supports_256color, supports_truecolor};
+use gix::Repository;
+use std::path::Path;
 
 const BASH_WRAPPERS: NonPrintingWrappers = ("\\[", "\\]");
 const ZSH_WRAPPERS: NonPrintingWrappers = ("%{", "%}");
@@ -11,9 +23,38 @@ const ZSH_WRAPPERS: NonPrintingWrappers = ("%{", "%}");
 pub fn print_prompt(shell: &str) {
     let term_bg = term_background().unwrap_or(Srgb::WHITE);
     let (path_bg, git_bg) = prompt_bg_colors(term_bg);
+    let warning = prompt_warning_color(term_bg);
 
     let repo = current_repo();
-    let workdir = repo.as_ref().and_then(|r| r.workdir());
+
+    // The innermost checkout wins when hg, jj and git repos are nested, and a jj workspace
+    // colocated with git is shown as jj, since git only sees a detached HEAD there
+    let git_workdir = repo
+        .as_ref()
+        .and_then(|r| r.workdir().map(Path::to_path_buf));
+    let jj = current_jj_workspace(git_workdir.as_deref())
+        .filter(|j| git_workdir.as_ref().is_none_or(|w| j.starts_with(w)));
+    let hg = current_hg_repo(jj.as_deref().or(git_workdir.as_deref())).filter(|h| {
+        [&git_workdir, &jj].iter().all(|outer| {
+            outer
+                .as_ref()
+                .is_none_or(|o| h.root.starts_with(o) && &h.root != o)
+        })
+    });
+    let jj = if hg.is_some() { None } else { jj };
+    let repo = if hg.is_some() || jj.is_some() {
+        None
+    } else {
+        repo
+    };
+
+    // Bare repos have no workdir, so paths are shown relative to the repo itself
+    let workdir = match (&hg, &jj) {
+        (Some(h), _) => Some(h.root.as_path()),
+        (_, Some(j)) => Some(j.as_path()),
+        _ => repo.as_ref().map(|r| r.workdir().unwrap_or(r.git_dir())),
+    };
+    let superproject = repo.as_ref().and_then(repo_superproject);
 
     let is_dark = Oklab::from(term_bg).is_dark();
 
@@ -26,32 +67,227 @@ pub fn print_prompt(shell: &str) {
     let mut b = TextBuilder::new();
     b.fg(Some(path_bg)).text("\n\u{e0b6}");
 
-    let path = displayed_path(workdir).unwrap_or("🆘".into());
+    let superproject_workdir = superproject.as_ref().map(|s| s.workdir.as_path());
+    let path = displayed_path(workdir, superproject_workdir).unwrap_or("🆘".into());
     b.fg(None).bg(Some(path_bg)).text(&format!(" {path} "));
 
     b.fg(Some(path_bg)).bg(Some(git_bg)).text("\u{e0b0}");
 
     if let Some(r) = &repo
+        && !is_untracked_home_dir(r)
         && let Some(head) = repo_head_description(r)
     {
-        b.fg(None).text(" 🌵 ");
+        if is_repo_trusted(r) {
+            let colors = (green, warning);
+            git_segment(&mut b, r, &head, superproject.as_ref(), is_dark, colors);
+        } else {
+            // Only HEAD is read from a repo owned by someone else, like git itself does
+            b.fg(None).text(" 🌵 ");
+            b.bold(is_dark).fg(green).text("⊘ ").bold(false);
+            b.fg(None).text(&format!("{head} "));
+        }
+    } else if let Some(h) = &hg
+        && let Some(head) = hg_head_description(h)
+    {
+        b.fg(None).text(" ☿ ");
+        state_marker(&mut b, hg_repo_state(h), is_dark, green);
+        b.fg(None).text(&format!("{head} "));
+        dirty_marker(&mut b, check_hg_dirty(h), is_dark, green);
+    } else if let Some(j) = &jj {
+        jj_segment(&mut b, j, is_dark, green);
+    }
 
-        if let Some(s) = repo_state(r) {
-            b.bold(is_dark).fg(green).text(&format!("{s} ")).bold(false);
+    b.fg(Some(git_bg)).bg(None).text("\u{e0b0}\n");
+    b.bold(true).fg(green).text("\u{276f} ");
+
+    let prompt = b.build().render(wrappers_for_shell(shell));
+    print!("{prompt}");
+}
+
+fn git_segment(
+    b: &mut TextBuilder,
+    r: &Repository,
+    head: &str,
+    superproject: Option<&Superproject>,
+    is_dark: bool,
+    (green, warning): (Option<TermColor>, Option<TermColor>),
+) {
+    let worktree = repo_linked_worktree(r);
+    let web = origin_web(r);
+    let icon = match (&worktree, &web) {
+        (Some(_), _) => "🌳",
+        (None, Some(w)) => w.forge.icon(),
+        (None, None) => "🌵",
+    };
+    // The icon opens the repo's page in terminals that support hyperlinks
+    b.fg(None).text(" ");
+    b.link(web.as_ref().map(|w| w.url.as_str()))
+        .text(icon)
+        .link(None);
+    b.text(" ");
+
+    if let Some(w) = &worktree {
+        b.fg(None).text(&format!("{}: ", w.name));
+        if w.is_locked {
+            b.text("🔒 ");
         }
+        if w.is_prunable {
+            b.bold(is_dark).fg(green).text("✂ ").bold(false);
+        }
+    }
 
+    state_marker(b, repo_state(r), is_dark, green);
+
+    // Named outright, since that's the file to remove
+    for lock in repo_stale_locks(r) {
+        if let Some(name) = lock.path.file_name() {
+            let name = name.to_string_lossy();
+            b.bold(true)
+                .fg(warning)
+                .text(&format!("{name} "))
+                .bold(false);
+        }
+    }
+
+    if let Some(m) = repo_git_dir_marker(r) {
+        b.bold(is_dark).fg(green).text(&format!("{m} ")).bold(false);
+    }
+
+    if is_head_detached(r) {
+        b.fg(None).text("➦ ");
+    }
+
+    // On a protected branch, the branch name and any dirt stand out as a warning
+    let is_protected = is_on_protected_branch(r);
+    if is_protected {
+        b.bold(true).fg(warning).text(head).bold(false).text(" ");
+    } else {
         b.fg(None).text(&format!("{head} "));
+    }
+
+    if let Some(age) = head_commit_age(r) {
+        b.fg(None).text(&format!("{} ", short_age(age)));
+    }
+
+    if superproject.is_some_and(|s| s.records_other_commit) {
+        b.bold(is_dark).fg(green).text("≠ ").bold(false);
+    }
+
+    if is_branch_checked_out_elsewhere(r) {
+        b.bold(is_dark).fg(green).text("⇄ ").bold(false);
+    }
+
+    let dirty = check_repo_dirty(r);
+    let dirt_color = if is_protected { warning } else { green };
+    dirty_marker(b, dirty.dirtiness, is_dark, dirt_color);
+
+    if let Some(stat) = dirty.diff_stat
+        && stat != DiffStat::default()
+    {
+        b.fg(None)
+            .text(&format!("+{} -{} ", stat.insertions, stat.deletions));
+    }
 
-        if is_repo_dirty(r) {
+    if let Some(d) = default_branch_divergence(r)
+        && (d.ahead > 0 || d.behind > 0)
+    {
+        let more = |truncated| if truncated { "+" } else { "" };
+        let ahead = format!("{}{}", d.ahead, more(d.is_ahead_truncated));
+        let behind = format!("{}{}", d.behind, more(d.is_behind_truncated));
+        b.fg(None).text(&format!("{} ↑{ahead}↓{behind} ", d.base));
+    }
+
+    let shape = repo_shape(r);
+    if shape.shallow_commits.is_some() {
+        b.fg(None).text("◌ ");
+    }
+    if shape.sparse_checkout.is_some() {
+        b.fg(None).text("◐ ");
+    }
+    if shape.is_partial_clone() {
+        b.fg(None).text("◔ ");
+    }
+
+    if is_fetch_stale(r) {
+        b.fg(None).text("⌛ ");
+    }
+
+    let squashes = pending_squash_count(r).unwrap_or(0);
+    if squashes > 0 {
+        b.fg(None).text(&format!("✎{squashes} "));
+    }
+
+    if repo_identity_problem(r).is_some() {
+        b.bold(true).fg(warning).text("✉ ").bold(false);
+    }
+
+    let stashes = repo_stash_count(r);
+    if stashes > 0 {
+        b.fg(None).text(&format!("⚑{stashes} "));
+    }
+}
+
+fn jj_segment(b: &mut TextBuilder, workspace: &Path, is_dark: bool, green: Option<TermColor>) {
+    b.fg(None).text(" 🥋 ");
+
+    let Some(wc) = jj_working_copy(workspace) else {
+        b.text("@ ");
+        return;
+    };
+
+    let (prefix, rest) = &wc.change_id;
+    b.bold(true)
+        .text(prefix)
+        .bold(false)
+        .text(&format!("{rest} "));
+
+    for bookmark in &wc.bookmarks {
+        b.text(&format!("{bookmark} "));
+    }
+
+    if wc.has_conflict {
+        b.bold(is_dark).fg(green).text("× ").bold(false);
+    }
+    if wc.is_empty {
+        b.fg(None).text("∅ ");
+    }
+}
+
+fn state_marker(b: &mut TextBuilder, state: Option<&str>, is_dark: bool, green: Option<TermColor>) {
+    if let Some(s) = state {
+        b.bold(is_dark).fg(green).text(&format!("{s} ")).bold(false);
+    }
+}
+
+fn dirty_marker(
+    b: &mut TextBuilder,
+    dirtiness: Dirtiness,
+    is_dark: bool,
+    green: Option<TermColor>,
+) {
+    match dirtiness {
+        Dirtiness::Dirty => {
             b.bold(is_dark).fg(green).text("! ").bold(false);
         }
+        Dirtiness::Unknown => {
+            b.fg(None).text("? ");
+        }
+        Dirtiness::Clean => {}
     }
+}
 
-    b.fg(Some(git_bg)).bg(None).text("\u{e0b0}\n");
-    b.bold(true).fg(green).text("\u{276f} ");
+fn prompt_warning_color(term_bg: Srgb) -> Option<TermColor> {
+    let is_dark = Oklab::from(term_bg).is_dark();
 
-    let prompt = b.build().render(wrappers_for_shell(shell));
-    print!("{prompt}");
+    if supports_truecolor() {
+        Some(TermColor::from(prompt_oklab_warning_color(term_bg)))
+    } else if supports_256color() {
+        Some(TermColor::Palette(prompt_256color_warning_color(term_bg)))
+    } else if is_dark {
+        Some(TermColor::Palette(11))
+    } else {
+        Some(TermColor::Palette(3))
+    }
 }
 
 fn prompt_bg_colors(term_bg: Srgb) -> (TermColor, TermColor) {
@@ -1,14 +1,48 @@
+mod cache;
+mod forge;
+mod fsmonitor;
+mod untracked_cache;
+
+use gix::ObjectId;
+use gix::bstr::BString;
+use gix::commit::describe::SelectRef;
+use gix::diff::blob::pipeline::{Mode, WorktreeRoots};
+use gix::diff::blob::platform::prepare_diff::Operation;
+use gix::diff::blob::sink::Counter;
+use gix::diff::blob::{ResourceKind, diff};
+use gix::discover::upwards;
+use gix::glob::wildmatch;
+use gix::glob::wildmatch::Mode as WildmatchMode;
 use gix::head::Kind;
+use gix::objs::tree::EntryKind;
 use gix::progress::Discard;
+use gix::refs::FullName;
+use gix::remote::Direction;
+use gix::repository::Kind as RepoKind;
+use gix::sec::Trust;
 use gix::state::InProgress;
+use gix::status::tree_index::TrackRenames;
 use gix::status::{Submodule, UntrackedFiles};
-use gix::{Repository, discover};
+use gix::submodule::config::Ignore;
+use gix::{Repository, ThreadSafeRepository, discover};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
 };
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::utils::current_path;
+use crate::daemon::query_daemon;
+use crate::utils::{current_path, env_git_dir, env_git_discovery_across_filesystem, home_path};
+pub use cache::global_excludes_file;
+pub use forge::origin_web;
+pub use fsmonitor::{FsMonitor, configured_fsmonitor};
+use fsmonitor::{changes_since, current_token};
+pub use untracked_cache::cached_has_untracked_files;
 
 pub fn repo_state(repo: &Repository) -> Option<&str> {
     match repo.state()? {
@@ -21,27 +55,511 @@ pub fn repo_state(repo: &Repository) -> Option<&str> {
     }
 }
 
-pub fn is_repo_dirty(repo: &Repository) -> bool {
-    check_dirty(repo).unwrap_or(false)
+// Mirrors the markers of git-prompt.sh, which hint that git commands behave differently here
+pub fn repo_git_dir_marker(repo: &Repository) -> Option<&str> {
+    if repo.is_bare() {
+        return Some("BARE:");
+    }
+
+    let cwd = current_path()?.canonicalize().ok()?;
+    let git_dir = repo.git_dir().canonicalize().ok()?;
+    cwd.starts_with(git_dir).then_some("GIT_DIR!")
 }
 
-fn check_dirty(repo: &Repository) -> Option<bool> {
-    let had_enough = Arc::new(AtomicBool::new(false));
+pub fn is_repo_trusted(repo: &Repository) -> bool {
+    repo.git_dir_trust() == Trust::Full
+}
+
+// A dotfiles checkout in $HOME would otherwise claim every directory below it
+pub fn is_untracked_home_dir(repo: &Repository) -> bool {
+    check_untracked_home_dir(repo).unwrap_or(false)
+}
+
+fn check_untracked_home_dir(repo: &Repository) -> Option<bool> {
+    // An explicit GIT_DIR means the user asked for this repo
+    if env_git_dir().is_some() {
+        return Some(false);
+    }
+
+    let workdir = repo.workdir()?;
+    if !same_path(workdir, &home_path()?) {
+        return Some(false);
+    }
+
+    let cwd = current_path()?;
+    let rel = cwd.strip_prefix(workdir).ok()?;
+    if rel.as_os_str().is_empty() {
+        return Some(false);
+    }
+
+    let prefix = format!("{}/", rel.to_str()?);
+    let index = repo.index_or_empty().ok()?;
+    let tracked = index.prefixed_entries(prefix.as_str().into());
+    Some(tracked.is_none_or(|entries| entries.is_empty()))
+}
+
+// Past this, the prompt would feel stuck, so the status walk is abandoned
+pub const DIRTY_CHECK_BUDGET: Duration = Duration::from_millis(300);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Dirtiness {
+    Clean,
+    Dirty,
+    Unknown,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DirtySource {
+    Daemon,
+    Cache,
+    FsMonitor,
+    Walk,
+    Disabled,
+}
+
+// Read from git-prompt.sh's and git's own keys, with gigawatt.* ones taking precedence
+#[derive(Copy, Clone, PartialEq, Hash, Debug)]
+pub struct DirtyCheckConfig {
+    pub show_dirty_state: bool,
+    pub show_untracked_files: bool,
+    // None leaves it to each submodule's own ignore setting
+    pub ignore_submodules: Option<Ignore>,
+}
+
+pub fn dirty_check_config(repo: &Repository) -> DirtyCheckConfig {
+    let config = repo.config_snapshot();
+    let boolean = |keys: &[&str]| keys.iter().find_map(|&k| config.boolean(k));
+
+    let show_dirty_state =
+        boolean(&["gigawatt.showDirtyState", "bash.showDirtyState"]).unwrap_or(true);
+
+    let show_untracked_files = boolean(&["gigawatt.showUntrackedFiles", "bash.showUntrackedFiles"])
+        .or_else(|| {
+            let value = config.string("status.showUntrackedFiles")?;
+            match value.to_string().as_str() {
+                "no" | "false" | "off" | "0" => Some(false),
+                _ => Some(true),
+            }
+        })
+        .unwrap_or(true);
+
+    let ignore_submodules = ["gigawatt.ignoreSubmodules", "diff.ignoreSubmodules"]
+        .iter()
+        .find_map(|&k| Ignore::try_from(config.string(k)?.as_ref()).ok());
+
+    DirtyCheckConfig {
+        show_dirty_state,
+        show_untracked_files,
+        ignore_submodules,
+    }
+}
+
+pub struct DirtyCheck {
+    pub dirtiness: Dirtiness,
+    // Kept with a dirty result, so that a cached one doesn't need a walk for it either
+    pub diff_stat: Option<DiffStat>,
+    pub elapsed: Duration,
+    pub source: DirtySource,
+}
+
+pub fn check_repo_dirty(repo: &Repository) -> DirtyCheck {
+    let start = Instant::now();
+    let check = |dirtiness, diff_stat, source| DirtyCheck {
+        dirtiness,
+        diff_stat,
+        elapsed: start.elapsed(),
+        source,
+    };
+
+    let config = dirty_check_config(repo);
+    if !config.show_dirty_state {
+        return check(Dirtiness::Clean, None, DirtySource::Disabled);
+    }
+
+    // The daemon keys repos by workdir, which doesn't hold for repos set up through GIT_DIR
+    if env_git_dir().is_none()
+        && let Some(workdir) = repo.workdir()
+        && let Some((dirtiness, diff_stat)) = query_daemon(workdir)
+    {
+        // A daemon that couldn't count the lines leaves it to the prompt
+        let diff_stat = match dirtiness {
+            Dirtiness::Dirty => diff_stat.or_else(|| repo_diff_stat(repo, DIFF_STAT_BUDGET)),
+            _ => None,
+        };
+        return check(dirtiness, diff_stat, DirtySource::Daemon);
+    }
+
+    // gix's status doesn't read the fsmonitor index extension, so the monitor instead vouches
+    // for the cached result, like it does for git's stat data. The fingerprints share the
+    // walk's budget, and a workdir too big to fingerprint in time just goes uncached.
+    let monitor = configured_fsmonitor(repo);
+    let deadline = start + DIRTY_CHECK_BUDGET;
+    let cached = cache::cached_status(repo, &config, monitor.is_none(), deadline);
+
+    match (&monitor, cached) {
+        (None, Some(cached)) => {
+            return check(cached.dirtiness, cached.diff_stat, DirtySource::Cache);
+        }
+        (Some(m), Some(cached)) => {
+            if let Some(token) = &cached.fsmonitor_token
+                && let Some(answer) = changes_since(repo, m, token)
+                && !answer.has_changes
+            {
+                cache::store_status(
+                    repo,
+                    &cached.snapshot,
+                    cached.dirtiness,
+                    cached.diff_stat,
+                    Some(&answer.token),
+                );
+                return check(cached.dirtiness, cached.diff_stat, DirtySource::FsMonitor);
+            }
+        }
+        (_, None) => {}
+    }
+
+    let token = monitor.as_ref().and_then(|m| current_token(repo, m));
+    let snapshot = cache::snapshot(repo, &config, monitor.is_none(), deadline);
+    let dirtiness = walk_dirtiness(repo, deadline.saturating_duration_since(Instant::now()));
+    let diff_stat = match dirtiness {
+        Dirtiness::Dirty => repo_diff_stat(repo, DIFF_STAT_BUDGET),
+        _ => None,
+    };
+    if let Some(snapshot) = snapshot
+        && (monitor.is_none() || token.is_some())
+    {
+        cache::store_status(repo, &snapshot, dirtiness, diff_stat, token.as_deref());
+    }
+
+    check(dirtiness, diff_stat, DirtySource::Walk)
+}
+
+pub fn walk_dirtiness(repo: &Repository, budget: Duration) -> Dirtiness {
+    check_dirty(repo, budget).unwrap_or(Dirtiness::Clean)
+}
+
+fn check_dirty(repo: &Repository, budget: Duration) -> Option<Dirtiness> {
+    // Bare repos have nothing checked out to compare against
+    repo.workdir()?;
+    let config = dirty_check_config(repo);
+
+    // git's untracked cache can spare listing every directory, which gix would do otherwise
+    let untracked_files = if !config.show_untracked_files {
+        UntrackedFiles::None
+    } else {
+        match cached_has_untracked_files(repo) {
+            Some(true) => return Some(Dirtiness::Dirty),
+            Some(false) => UntrackedFiles::None,
+            None => UntrackedFiles::Collapsed,
+        }
+    };
+    let submodules = match config.ignore_submodules {
+        Some(ignore) => Submodule::Given {
+            ignore,
+            check_dirty: true,
+        },
+        None => Submodule::AsConfigured { check_dirty: true },
+    };
+
+    let (had_enough, timed_out) = interrupt_after(budget);
 
     let platform = repo
         .status(Discard)
         .ok()?
-        .untracked_files(UntrackedFiles::Collapsed)
-        .index_worktree_submodules(Submodule::AsConfigured { check_dirty: true })
+        .untracked_files(untracked_files)
+        .index_worktree_submodules(submodules)
         .should_interrupt_owned(had_enough.clone());
 
     let mut it = platform.into_iter(std::iter::empty()).ok()?;
-    let dirty = it.next().is_some();
-    if dirty {
-        had_enough.store(true, Ordering::Relaxed);
+    let change = it.next();
+    had_enough.store(true, Ordering::Relaxed);
+
+    if timed_out.load(Ordering::Relaxed) {
+        Some(Dirtiness::Unknown)
+    } else if change.is_some() {
+        Some(Dirtiness::Dirty)
+    } else {
+        Some(Dirtiness::Clean)
+    }
+}
+
+// Raises both flags once the budget is spent; the caller raises had_enough itself when done
+fn interrupt_after(budget: Duration) -> (Arc<AtomicBool>, Arc<AtomicBool>) {
+    let had_enough = Arc::new(AtomicBool::new(false));
+    let timed_out = Arc::new(AtomicBool::new(false));
+
+    thread::spawn({
+        let had_enough = had_enough.clone();
+        let timed_out = timed_out.clone();
+        move || {
+            thread::sleep(budget);
+            timed_out.store(true, Ordering::Relaxed);
+            had_enough.store(true, Ordering::Relaxed);
+        }
+    });
+
+    (had_enough, timed_out)
+}
+
+// Line counts are only worth a glance, so they get less time than the dirty check
+pub const DIFF_STAT_BUDGET: Duration = Duration::from_millis(150);
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct DiffStat {
+    pub insertions: u32,
+    pub deletions: u32,
+}
+
+// Stored and passed around as "insertions,deletions"
+pub fn parse_diff_stat(stat: &str) -> Option<DiffStat> {
+    let (insertions, deletions) = stat.split_once(',')?;
+    Some(DiffStat {
+        insertions: insertions.parse().ok()?,
+        deletions: deletions.parse().ok()?,
+    })
+}
+
+// Like `git diff HEAD --shortstat`, so staged and unstaged changes count together
+pub fn repo_diff_stat(repo: &Repository, budget: Duration) -> Option<DiffStat> {
+    let workdir = repo.workdir()?.to_path_buf();
+    let (had_enough, timed_out) = interrupt_after(budget);
+
+    let platform = repo
+        .status(Discard)
+        .ok()?
+        .untracked_files(UntrackedFiles::None)
+        .index_worktree_submodules(Submodule::Given {
+            ignore: Ignore::All,
+            check_dirty: false,
+        })
+        .tree_index_track_renames(TrackRenames::Disabled)
+        .should_interrupt_owned(had_enough.clone());
+
+    let paths: BTreeSet<BString> = platform
+        .into_iter(std::iter::empty())
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|item| item.location().to_owned())
+        .collect();
+
+    let head_tree = repo.head_tree().ok();
+    let roots = WorktreeRoots {
+        old_root: None,
+        new_root: Some(workdir.clone()),
+    };
+    let mut cache = repo.diff_resource_cache(Mode::ToGit, roots).ok()?;
+    let mut stat = DiffStat::default();
+
+    for path in paths {
+        if timed_out.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let old = head_tree.as_ref().and_then(|t| {
+            t.lookup_entry_by_path(gix::path::from_bstr(&path))
+                .ok()
+                .flatten()
+        });
+        let (old_id, old_kind) = old
+            .map(|e| (e.object_id(), e.mode().kind()))
+            .unwrap_or((ObjectId::null(repo.object_hash()), EntryKind::Blob));
+
+        let Ok(meta) = workdir.join(gix::path::from_bstr(&path)).symlink_metadata() else {
+            // Deleted in the worktree, so every line of the old version goes
+            if let Ok(blob) = repo.find_blob(old_id) {
+                stat.deletions += line_count(&blob.data);
+            }
+            continue;
+        };
+        let new_kind = if meta.is_symlink() {
+            EntryKind::Link
+        } else {
+            EntryKind::Blob
+        };
+
+        // A null id makes the pipeline read the file from the worktree
+        let null = ObjectId::null(repo.object_hash());
+        let (old, new) = (ResourceKind::OldOrSource, ResourceKind::NewOrDestination);
+        let objects = &repo.objects;
+        if cache
+            .set_resource(old_id, old_kind, path.as_ref(), old, objects)
+            .is_err()
+            || cache
+                .set_resource(null, new_kind, path.as_ref(), new, objects)
+                .is_err()
+        {
+            continue;
+        }
+
+        // Binary files have no lines to count, like in git's stat
+        if let Ok(outcome) = cache.prepare_diff()
+            && let Operation::InternalDiff { algorithm } = outcome.operation
+        {
+            let input = outcome.interned_input();
+            let counter = diff(algorithm, &input, Counter::default());
+            stat.insertions += counter.insertions;
+            stat.deletions += counter.removals;
+        }
     }
 
-    Some(dirty)
+    had_enough.store(true, Ordering::Relaxed);
+    (!timed_out.load(Ordering::Relaxed)).then_some(stat)
+}
+
+fn line_count(data: &[u8]) -> u32 {
+    if data.contains(&0) {
+        return 0;
+    }
+    let newlines = data.iter().filter(|&&b| b == b'\n').count();
+    let unterminated = data.last().is_some_and(|&b| b != b'\n');
+    (newlines + usize::from(unterminated)) as u32
+}
+
+pub fn head_commit_age(repo: &Repository) -> Option<Duration> {
+    let seconds = repo.head_commit().ok()?.time().ok()?.seconds;
+    let committed = UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?);
+    // A commit from a clock that runs ahead is just new
+    Some(
+        SystemTime::now()
+            .duration_since(committed)
+            .unwrap_or_default(),
+    )
+}
+
+// Past this, ahead and behind counts may well be out of date
+const STALE_FETCH_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub fn fetch_head_age(repo: &Repository) -> Option<Duration> {
+    // Each worktree records its own fetches
+    let modified = [repo.git_dir(), repo.common_dir()]
+        .iter()
+        .find_map(|dir| dir.join("FETCH_HEAD").metadata().ok())?
+        .modified()
+        .ok()?;
+    Some(
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default(),
+    )
+}
+
+pub fn is_fetch_stale(repo: &Repository) -> bool {
+    fetch_head_age(repo).is_some_and(|age| age > STALE_FETCH_AGE)
+}
+
+// Commits that `git rebase --autosquash` folds away, or that were never meant to be kept
+pub fn pending_squash_count(repo: &Repository) -> Option<usize> {
+    let branch = repo.head_name().ok()??;
+    let upstream = match repo.branch_remote_tracking_ref_name(branch.as_ref(), Direction::Fetch) {
+        Some(Ok(name)) => name.into_owned(),
+        _ => remote_default_branch(repo)?,
+    };
+
+    let head_id = repo.head_id().ok()?.detach();
+    let upstream_id = repo
+        .find_reference(&upstream)
+        .ok()?
+        .peel_to_id_in_place()
+        .ok()?;
+    let walk = repo
+        .rev_walk([head_id])
+        .with_hidden([upstream_id.detach()])
+        .all()
+        .ok()?;
+
+    let count = walk
+        .take(DIVERGENCE_WALK_LIMIT)
+        .filter_map(Result::ok)
+        .filter(|info| {
+            info.object()
+                .ok()
+                .and_then(|c| {
+                    c.message()
+                        .ok()
+                        .map(|m| is_squash_subject(&m.title.to_string()))
+                })
+                .unwrap_or(false)
+        })
+        .count();
+    Some(count)
+}
+
+fn is_squash_subject(subject: &str) -> bool {
+    let wip = subject
+        .get(..3)
+        .is_some_and(|p| p.eq_ignore_ascii_case("wip"))
+        && !subject[3..].starts_with(|c: char| c.is_alphanumeric());
+
+    wip || ["fixup! ", "squash! ", "amend! "]
+        .iter()
+        .any(|p| subject.starts_with(p))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum IdentityProblem {
+    Unset,
+    UnexpectedEmail,
+}
+
+// Catches commits made with a personal address in a work repo, or the other way around.
+// gigawatt.expectedEmail holds globs like *@example.com, any of which may match. The
+// identities are resolved like git does, so GIT_AUTHOR_*, GIT_COMMITTER_* and EMAIL count too.
+pub fn repo_identity_problem(repo: &Repository) -> Option<IdentityProblem> {
+    let emails: Option<Vec<BString>> = [repo.author(), repo.committer()]
+        .into_iter()
+        .map(|signature| {
+            let signature = signature?.ok()?;
+            let is_set = !signature.name.is_empty() && !signature.email.is_empty();
+            is_set.then(|| signature.email.to_owned())
+        })
+        .collect();
+    let Some(emails) = emails else {
+        return Some(IdentityProblem::Unset);
+    };
+
+    let config = repo.config_snapshot();
+    let patterns = config.plumbing().strings("gigawatt.expectedEmail")?;
+    let matches = |email: &BString| {
+        patterns
+            .iter()
+            .any(|p| wildmatch(p.as_ref(), email.as_ref(), WildmatchMode::IGNORE_CASE))
+    };
+    (!emails.iter().all(matches)).then_some(IdentityProblem::UnexpectedEmail)
+}
+
+// Git holds these for milliseconds, so one this old was left behind by a crashed process
+const STALE_LOCK_AGE: Duration = Duration::from_secs(60);
+
+pub struct StaleLock {
+    pub path: PathBuf,
+    pub age: Duration,
+}
+
+pub fn repo_stale_locks(repo: &Repository) -> Vec<StaleLock> {
+    let mut index_lock = repo.index_path().into_os_string();
+    index_lock.push(".lock");
+
+    [PathBuf::from(index_lock), repo.git_dir().join("HEAD.lock")]
+        .into_iter()
+        .filter_map(|path| {
+            let modified = path.symlink_metadata().ok()?.modified().ok()?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            (age > STALE_LOCK_AGE).then_some(StaleLock { path, age })
+        })
+        .collect()
+}
+
+pub fn repo_stash_count(repo: &Repository) -> usize {
+    count_stashes(repo).unwrap_or(0)
+}
+
+fn count_stashes(repo: &Repository) -> Option<usize> {
+    let stash = repo.try_find_reference("refs/stash").ok()??;
+    let mut log = stash.log_iter();
+    let count = log.all().ok()??.filter(Result::is_ok).count();
+    Some(count)
 }
 
 pub fn repo_head_description(repo: &Repository) -> Option<String> {
@@ -49,15 +567,420 @@ pub fn repo_head_description(repo: &Repository) -> Option<String> {
     match head.kind {
         Kind::Symbolic(r) => Some(r.name.shorten().to_string()),
         Kind::Detached { target, peeled } => {
-            Some(peeled.unwrap_or(target).to_hex_with_len(7).to_string())
+            let id = peeled.unwrap_or(target);
+            let name = detached_head_name(repo, id);
+            Some(name.unwrap_or_else(|| id.to_hex_with_len(7).to_string()))
         }
         Kind::Unborn(u) => Some(u.shorten().to_string()),
     }
 }
 
+const PROTECTED_BRANCHES: [&str; 3] = ["main", "master", "trunk"];
+
+// Work on these normally goes through a feature branch, so committing here is often a slip
+pub fn is_on_protected_branch(repo: &Repository) -> bool {
+    let Some(branch) = repo.head_name().ok().flatten() else {
+        return false;
+    };
+    let branch = branch.shorten().to_string();
+
+    PROTECTED_BRANCHES.contains(&branch.as_str())
+        || remote_default_branch(repo).is_some_and(|d| d.shorten() == format!("origin/{branch}"))
+}
+
+// What origin/HEAD points at, or the first conventional name the remote has
+fn remote_default_branch(repo: &Repository) -> Option<FullName> {
+    let existing = |name: &str| Some(repo.try_find_reference(name).ok()??.name().to_owned());
+
+    // A branch deleted on the remote can leave origin/HEAD dangling
+    if let Some(head) = repo.try_find_reference("refs/remotes/origin/HEAD").ok()?
+        && let Some(name) = head.target().try_name()
+        && let Some(name) = existing(name.as_bstr().to_string().as_str())
+    {
+        return Some(name);
+    }
+
+    PROTECTED_BRANCHES
+        .iter()
+        .find_map(|b| existing(&format!("refs/remotes/origin/{b}")))
+}
+
+// Walking further than this would stall the prompt on very old branches
+const DIVERGENCE_WALK_LIMIT: usize = 1000;
+
+pub struct Divergence {
+    pub base: String,
+    pub ahead: usize,
+    pub behind: usize,
+    pub is_ahead_truncated: bool,
+    pub is_behind_truncated: bool,
+}
+
+pub fn default_branch_divergence(repo: &Repository) -> Option<Divergence> {
+    let branch = repo.head_name().ok()??;
+    let default = remote_default_branch(repo)?;
+    let base = default.shorten().to_string();
+    if base == format!("origin/{}", branch.shorten()) {
+        return None;
+    }
+
+    let head_id = repo.head_id().ok()?.detach();
+    let default_id = repo
+        .find_reference(&default)
+        .ok()?
+        .peel_to_id_in_place()
+        .ok()?
+        .detach();
+
+    let count = |tip: ObjectId, hidden: ObjectId| {
+        let walk = repo.rev_walk([tip]).with_hidden([hidden]).all().ok()?;
+        Some(
+            walk.take(DIVERGENCE_WALK_LIMIT + 1)
+                .filter(Result::is_ok)
+                .count(),
+        )
+    };
+    let ahead = count(head_id, default_id)?;
+    let behind = count(default_id, head_id)?;
+
+    Some(Divergence {
+        base: base.trim_start_matches("origin/").to_string(),
+        ahead: ahead.min(DIVERGENCE_WALK_LIMIT),
+        behind: behind.min(DIVERGENCE_WALK_LIMIT),
+        is_ahead_truncated: ahead > DIVERGENCE_WALK_LIMIT,
+        is_behind_truncated: behind > DIVERGENCE_WALK_LIMIT,
+    })
+}
+
+pub fn is_head_detached(repo: &Repository) -> bool {
+    repo.head().is_ok_and(|h| h.is_detached())
+}
+
+fn detached_head_name(repo: &Repository, id: ObjectId) -> Option<String> {
+    let commit = repo.find_commit(id).ok()?;
+    let describe = commit
+        .describe()
+        .names(SelectRef::AllTags)
+        .try_format()
+        .ok()
+        .flatten();
+
+    if let Some(d) = &describe
+        && d.is_exact_match()
+    {
+        return d.name.as_ref().map(|n| n.to_string());
+    }
+
+    if let Some(remote_branch) = remote_branch_at(repo, id) {
+        return Some(remote_branch);
+    }
+
+    let d = describe?;
+    Some(describe_name(&d.name?.to_string(), d.depth))
+}
+
+fn remote_branch_at(repo: &Repository, id: ObjectId) -> Option<String> {
+    let refs = repo.references().ok()?;
+    let mut names: Vec<String> = refs
+        .remote_branches()
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|r| !r.name().as_bstr().ends_with(b"/HEAD"))
+        .filter_map(|mut r| {
+            let peeled = r.peel_to_id_in_place().ok()?;
+            (peeled == id).then(|| r.name().shorten().to_string())
+        })
+        .collect();
+
+    // Prefer the conventional remote when several point at the same commit
+    names.sort_by_key(|n| (!n.starts_with("origin/"), n.clone()));
+    names.into_iter().next()
+}
+
+fn describe_name(tag: &str, depth: u32) -> String {
+    if depth == 0 {
+        tag.into()
+    } else {
+        format!("{tag}+{depth}")
+    }
+}
+
+pub struct RepoShape {
+    pub shallow_commits: Option<usize>,
+    pub sparse_checkout: Option<&'static str>,
+    pub promisor_remotes: Vec<String>,
+}
+
+impl RepoShape {
+    pub fn is_partial_clone(&self) -> bool {
+        !self.promisor_remotes.is_empty()
+    }
+}
+
+pub fn repo_shape(repo: &Repository) -> RepoShape {
+    let config = repo.config_snapshot();
+
+    let shallow_commits = repo
+        .shallow_commits()
+        .ok()
+        .flatten()
+        .map(|commits| commits.len());
+
+    let sparse_checkout = config
+        .boolean("core.sparseCheckout")
+        .unwrap_or(false)
+        .then(|| {
+            if config.boolean("core.sparseCheckoutCone").unwrap_or(false) {
+                "cone"
+            } else {
+                "patterns"
+            }
+        });
+
+    // Older git versions name the single promisor remote in extensions.partialClone
+    let legacy_promisor = config.string("extensions.partialClone");
+    let promisor_remotes = repo
+        .remote_names()
+        .into_iter()
+        .filter(|name| {
+            config
+                .boolean(format!("remote.{name}.promisor").as_str())
+                .unwrap_or(false)
+                || legacy_promisor.as_deref() == Some(name.as_ref())
+        })
+        .map(|name| name.to_string())
+        .collect();
+
+    RepoShape {
+        shallow_commits,
+        sparse_checkout,
+        promisor_remotes,
+    }
+}
+
+pub struct LinkedWorktree {
+    pub name: String,
+    pub is_locked: bool,
+    pub is_prunable: bool,
+}
+
+pub fn repo_linked_worktree(repo: &Repository) -> Option<LinkedWorktree> {
+    if !matches!(repo.kind(), RepoKind::WorkTree { is_linked: true }) {
+        return None;
+    }
+
+    let proxy = repo
+        .worktrees()
+        .ok()?
+        .into_iter()
+        .find(|p| same_path(p.git_dir(), repo.git_dir()))?;
+
+    let is_locked = proxy.is_locked();
+
+    // Git prunes worktrees whose recorded location is gone, which happens if one is moved by hand
+    let is_prunable = !is_locked && proxy.base().map_or(true, |base| !base.exists());
+
+    Some(LinkedWorktree {
+        name: proxy.id().to_string(),
+        is_locked,
+        is_prunable,
+    })
+}
+
+pub fn is_branch_checked_out_elsewhere(repo: &Repository) -> bool {
+    check_branch_checked_out_elsewhere(repo).unwrap_or(false)
+}
+
+fn check_branch_checked_out_elsewhere(repo: &Repository) -> Option<bool> {
+    let head = repo.head_name().ok()??;
+    let main = repo.main_repo().ok()?;
+
+    let mut others: Vec<Repository> = main
+        .worktrees()
+        .ok()?
+        .into_iter()
+        .filter(|p| !same_path(p.git_dir(), repo.git_dir()))
+        .filter_map(|p| p.into_repo_with_possibly_inaccessible_worktree().ok())
+        .collect();
+
+    if !main.is_bare() && !same_path(main.git_dir(), repo.git_dir()) {
+        others.push(main);
+    }
+
+    Some(
+        others
+            .iter()
+            .any(|r| r.head_name().ok().flatten().as_ref() == Some(&head)),
+    )
+}
+
+pub struct Superproject {
+    pub workdir: PathBuf,
+    pub records_other_commit: bool,
+}
+
+pub fn repo_superproject(repo: &Repository) -> Option<Superproject> {
+    let workdir = repo.workdir()?;
+    // A submodule has a gitlink file, or a git dir in its superproject's modules/ once absorbed
+    let is_gitlink = workdir.join(".git").is_file();
+    let in_modules = repo
+        .git_dir()
+        .ancestors()
+        .any(|dir| dir.file_name() == Some("modules".as_ref()));
+    if !is_gitlink && !in_modules {
+        return None;
+    }
+
+    let (p, _) = discover_upwards(workdir.parent()?).ok()?;
+    let (_, parent_workdir) = p.into_repository_and_work_tree_directories();
+    let parent = gix::open(parent_workdir?).ok()?;
+
+    let submodule = parent
+        .submodules()
+        .ok()??
+        .find(|s| s.work_dir().is_ok_and(|w| same_path(&w, workdir)))?;
+
+    // Compare against the index so that a staged submodule update counts as recorded
+    let recorded = match submodule.index_id() {
+        Ok(Some(id)) => Some(id),
+        _ => submodule.head_id().ok().flatten(),
+    };
+    let head = repo.head_id().ok().map(|id| id.detach());
+
+    Some(Superproject {
+        workdir: parent.workdir()?.to_path_buf(),
+        records_other_commit: recorded.is_some() && recorded != head,
+    })
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 pub fn current_repo() -> Option<Repository> {
     let cwd = current_path()?;
-    let (p, _) = discover::upwards(&cwd).ok()?;
-    let (_, workdir) = p.into_repository_and_work_tree_directories();
-    gix::open(workdir?).ok()
+
+    // Bare dotfiles setups point git at the repo with GIT_DIR and GIT_WORK_TREE
+    if env_git_dir().is_some() {
+        let repo = ThreadSafeRepository::open_with_environment_overrides(&cwd, Default::default());
+        return repo.ok().map(Repository::from);
+    }
+    let (p, _) = discover_upwards(&cwd).ok()?;
+    let (git_dir, workdir) = p.into_repository_and_work_tree_directories();
+    gix::open(workdir.unwrap_or(git_dir)).ok()
+}
+
+pub fn repo_discovery_outcome() -> Option<String> {
+    if env_git_dir().is_some() {
+        return Some("Skipped, GIT_DIR is set".into());
+    }
+
+    let cwd = current_path()?;
+    let outcome = match discover_upwards(&cwd) {
+        Ok((p, _)) => format!("Found {}", p.as_ref().display()),
+        Err(upwards::Error::NoGitRepository { .. }) => "Reached the filesystem root".into(),
+        Err(upwards::Error::NoGitRepositoryWithinCeiling {
+            path,
+            ceiling_height,
+        }) => {
+            let ceiling = path.ancestors().nth(ceiling_height.saturating_sub(1));
+            let ceiling = ceiling.unwrap_or(&path).display();
+            format!("Stopped at {ceiling} because of GIT_CEILING_DIRECTORIES")
+        }
+        Err(upwards::Error::NoGitRepositoryWithinFs { limit, .. }) => format!(
+            "Stopped before {} because it's on another filesystem, see GIT_DISCOVERY_ACROSS_FILESYSTEM",
+            limit.display()
+        ),
+        Err(e) => e.to_string(),
+    };
+
+    Some(outcome)
+}
+
+// Like git, stop at GIT_CEILING_DIRECTORIES and filesystem boundaries so slow network mounts
+// above the current directory aren't touched on every prompt. gix still looks inside a ceiling
+// directory itself, which git doesn't unless it's where the search started.
+fn discover_upwards(dir: &Path) -> Result<(discover::repository::Path, Trust), upwards::Error> {
+    let mut options = upwards::Options {
+        match_ceiling_dir_or_error: false,
+        ..Default::default()
+    }
+    .apply_environment();
+    options.cross_fs = env_git_discovery_across_filesystem();
+    let ceiling_dirs = options.ceiling_dirs.clone();
+    let (path, trust) = discover::upwards_opts(dir, options)?;
+
+    let found = match &path {
+        discover::repository::Path::LinkedWorkTree { work_dir, .. } => work_dir,
+        discover::repository::Path::WorkTree(dir) => dir,
+        discover::repository::Path::Repository(dir) if dir.ends_with(".git") => {
+            dir.parent().unwrap_or(dir)
+        }
+        discover::repository::Path::Repository(dir) => dir,
+    };
+    let found = found.canonicalize().unwrap_or_else(|_| found.to_path_buf());
+    let start = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    if found != start && ceiling_dirs.contains(&found) {
+        return Err(upwards::Error::NoGitRepositoryWithinCeiling {
+            path: start.clone(),
+            ceiling_height: start.ancestors().position(|d| d == found).unwrap_or(0) + 1,
+        });
+    }
+
+    Ok((path, trust))
+}
+
+// Looks for the checkout of another VCS with the same limits as git's own discovery, and no
+// further up than `outer`, the checkout already found, which anything above would contain
+pub fn find_upwards(marker: &str, outer: Option<&Path>) -> Option<PathBuf> {
+    let cwd = current_path()?;
+    let ceiling_dirs = upwards::Options::default().apply_environment().ceiling_dirs;
+    let cross_fs = env_git_discovery_across_filesystem();
+    let device = fs::metadata(&cwd).ok()?.dev();
+
+    for dir in cwd.ancestors() {
+        if dir != cwd && ceiling_dirs.iter().any(|c| c == dir) {
+            return None;
+        }
+        if !cross_fs && fs::metadata(dir).ok()?.dev() != device {
+            return None;
+        }
+        if dir.join(marker).is_dir() {
+            return Some(dir.to_path_buf());
+        }
+        if outer == Some(dir) {
+            return None;
+        }
+    }
+
+    None
+}
+
+#[test]
+fn test_describe_name() {
+    assert_eq!(describe_name("v1.2.0", 0), "v1.2.0");
+    assert_eq!(describe_name("v1.2.0", 3), "v1.2.0+3");
+}
+
+#[test]
+fn test_line_count() {
+    assert_eq!(line_count(b""), 0);
+    assert_eq!(line_count(b"a\nb\n"), 2);
+    assert_eq!(line_count(b"a\nb"), 2);
+    assert_eq!(line_count(b"a\0b\n"), 0);
+}
+
+#[test]
+fn test_is_squash_subject() {
+    assert!(is_squash_subject("fixup! Add the thing"));
+    assert!(is_squash_subject("squash! Add the thing"));
+    assert!(is_squash_subject("amend! Add the thing"));
+    assert!(is_squash_subject("WIP"));
+    assert!(is_squash_subject("wip: half done"));
+    assert!(!is_squash_subject("Wipe the cache"));
+    assert!(!is_squash_subject("Add fixup! handling"));
+    assert!(!is_squash_subject(""));
 }
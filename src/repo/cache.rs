@@ -0,0 +1,336 @@
+use crate::repo::{DiffStat, Dirtiness, DirtyCheckConfig, parse_diff_stat};
+use crate::utils::{cache_path, home_path};
+use gix::Repository;
+use gix::index::entry::Mode;
+use gix::worktree::stack::state::ignore::Source;
+use std::collections::BTreeSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{env, fs};
+
+// The fingerprint covers everything a status walk looks at, but only stats files instead of
+// hashing them or listing directories, which is where the walk spends its time in big repos.
+// With an fsmonitor watching the workdir, only the git dir needs a look. None past the
+// deadline, since a cache that takes longer than the walk isn't worth keeping.
+fn fingerprint(
+    repo: &Repository,
+    config: &DirtyCheckConfig,
+    stat_workdir: bool,
+    untracked_dirs: &[PathBuf],
+    deadline: Instant,
+) -> Option<u64> {
+    let workdir = repo.workdir()?;
+    let index = repo.index_or_empty().ok()?;
+    let mut hasher = DefaultHasher::new();
+    // The settings can come from global config, which the stats below don't cover
+    config.hash(&mut hasher);
+
+    // A linked worktree has its own HEAD, but shares the config and excludes with the others
+    let common_dir = repo.common_dir();
+    let global_excludes = global_excludes_file(repo);
+    global_excludes.hash(&mut hasher);
+    for path in [
+        Some(repo.index_path()),
+        Some(repo.git_dir().join("HEAD")),
+        Some(common_dir.join("config")),
+        Some(common_dir.join("info").join("exclude")),
+        global_excludes,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        hash_stat(&path, &mut hasher);
+    }
+    repo.head_id().ok().map(|id| id.detach()).hash(&mut hasher);
+
+    // New and removed files, including atomic saves by editors, show up as directory changes
+    let mut dirs = BTreeSet::from([PathBuf::new()]);
+
+    for (i, entry) in index.entries().iter().enumerate() {
+        // Changes inside submodules aren't visible from here
+        if entry.mode == Mode::COMMIT || (i % 256 == 0 && Instant::now() > deadline) {
+            return None;
+        }
+
+        if !stat_workdir {
+            continue;
+        }
+
+        let path = gix::path::from_bstr(entry.path(&index));
+        hash_stat(&workdir.join(&path), &mut hasher);
+        add_parent_dirs(&path, &mut dirs);
+    }
+
+    if stat_workdir {
+        dirs.extend(untracked_dirs.iter().cloned());
+    }
+    for (i, dir) in dirs.iter().enumerate() {
+        if i % 256 == 0 && Instant::now() > deadline {
+            return None;
+        }
+        let dir = workdir.join(dir);
+        hash_stat(&dir, &mut hasher);
+        // An untracked .gitignore can be edited in place without touching its directory
+        hash_stat(&dir.join(".gitignore"), &mut hasher);
+    }
+
+    Some(hasher.finish())
+}
+
+// The fingerprint of the workdir before a walk, so that changes during the walk make the
+// stored result stale rather than going unnoticed
+pub struct Snapshot {
+    fingerprint: u64,
+    untracked_dirs: Vec<PathBuf>,
+}
+
+pub fn snapshot(
+    repo: &Repository,
+    config: &DirtyCheckConfig,
+    stat_workdir: bool,
+    deadline: Instant,
+) -> Option<Snapshot> {
+    let untracked_dirs = if stat_workdir && config.show_untracked_files {
+        untracked_dirs(repo, deadline)?
+    } else {
+        Vec::new()
+    };
+
+    Some(Snapshot {
+        fingerprint: fingerprint(repo, config, stat_workdir, &untracked_dirs, deadline)?,
+        untracked_dirs,
+    })
+}
+
+// Directories that git neither tracks nor ignores. Files appearing in them change none of
+// the tracked directories, so they're listed once per walk and stored with its result.
+fn untracked_dirs(repo: &Repository, deadline: Instant) -> Option<Vec<PathBuf>> {
+    let workdir = repo.workdir()?;
+    let index = repo.index_or_empty().ok()?;
+    let mut excludes = repo
+        .excludes(&index, None, Source::WorktreeThenIdMappingIfNotSkipped)
+        .ok()?;
+
+    let mut tracked = BTreeSet::from([PathBuf::new()]);
+    for entry in index.entries() {
+        add_parent_dirs(&gix::path::from_bstr(entry.path(&index)), &mut tracked);
+    }
+
+    let mut pending: Vec<PathBuf> = tracked.iter().cloned().collect();
+    let mut found = Vec::new();
+
+    while let Some(dir) = pending.pop() {
+        if Instant::now() > deadline {
+            return None;
+        }
+        let Ok(entries) = fs::read_dir(workdir.join(&dir)) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+
+            let path = dir.join(entry.file_name());
+            if path == Path::new(".git")
+                || tracked.contains(&path)
+                || excludes.at_path(&path, Some(Mode::DIR)).ok()?.is_excluded()
+            {
+                continue;
+            }
+            // They're stored one per line
+            if path.to_str()?.contains('\n') {
+                return None;
+            }
+
+            // A nested repo is a single untracked entry to git
+            if !entry.path().join(".git").exists() {
+                pending.push(path.clone());
+            }
+            found.push(path);
+        }
+    }
+
+    found.sort();
+    Some(found)
+}
+
+fn add_parent_dirs(path: &Path, dirs: &mut BTreeSet<PathBuf>) {
+    for dir in path.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() || !dirs.insert(dir.to_path_buf()) {
+            break;
+        }
+    }
+}
+
+// Where git reads the user's own ignore rules from
+pub fn global_excludes_file(repo: &Repository) -> Option<PathBuf> {
+    if let Some(path) = repo.config_snapshot().trusted_path("core.excludesFile") {
+        return path.ok().map(|p| p.into_owned());
+    }
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| Some(home_path()?.join(".config")))?;
+    Some(config_home.join("git").join("ignore"))
+}
+
+pub struct CachedStatus {
+    pub dirtiness: Dirtiness,
+    pub diff_stat: Option<DiffStat>,
+    pub fsmonitor_token: Option<String>,
+    pub snapshot: Snapshot,
+}
+
+pub fn cached_status(
+    repo: &Repository,
+    config: &DirtyCheckConfig,
+    stat_workdir: bool,
+    deadline: Instant,
+) -> Option<CachedStatus> {
+    let contents = fs::read_to_string(cache_file(repo)?).ok()?;
+    let mut lines = contents.lines();
+    let mut fields = lines.next()?.splitn(4, ' ');
+
+    let stored = fields.next()?.parse::<u64>().ok()?;
+    let dirtiness = match fields.next()? {
+        "clean" => Dirtiness::Clean,
+        "dirty" => Dirtiness::Dirty,
+        _ => return None,
+    };
+    let diff_stat = match fields.next()? {
+        "-" => None,
+        stat => Some(parse_diff_stat(stat)?),
+    };
+    let fsmonitor_token = fields.next().map(String::from);
+
+    let untracked_dirs: Vec<PathBuf> = lines.map(PathBuf::from).collect();
+    let fingerprint = fingerprint(repo, config, stat_workdir, &untracked_dirs, deadline)?;
+    if fingerprint != stored {
+        return None;
+    }
+
+    Some(CachedStatus {
+        dirtiness,
+        diff_stat,
+        fsmonitor_token,
+        snapshot: Snapshot {
+            fingerprint,
+            untracked_dirs,
+        },
+    })
+}
+
+pub fn store_status(
+    repo: &Repository,
+    snapshot: &Snapshot,
+    dirtiness: Dirtiness,
+    diff_stat: Option<DiffStat>,
+    fsmonitor_token: Option<&str>,
+) {
+    let dirtiness = match dirtiness {
+        Dirtiness::Clean => "clean",
+        Dirtiness::Dirty => "dirty",
+        Dirtiness::Unknown => return,
+    };
+
+    if let Some(path) = cache_file(repo)
+        && let Some(dir) = path.parent()
+        && fs::create_dir_all(dir).is_ok()
+    {
+        // Write and rename, so that a concurrent prompt never reads half a file
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let fingerprint = snapshot.fingerprint;
+        let diff_stat = match diff_stat {
+            Some(s) => format!("{},{}", s.insertions, s.deletions),
+            None => "-".to_string(),
+        };
+        let mut contents = match fsmonitor_token {
+            Some(token) => format!("{fingerprint} {dirtiness} {diff_stat} {token}\n"),
+            None => format!("{fingerprint} {dirtiness} {diff_stat}\n"),
+        };
+        for dir in snapshot.untracked_dirs.iter().filter_map(|d| d.to_str()) {
+            contents.push_str(dir);
+            contents.push('\n');
+        }
+        if fs::write(&tmp, contents).is_ok() {
+            _ = fs::rename(&tmp, &path);
+        }
+    }
+}
+
+fn cache_file(repo: &Repository) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    repo.workdir()?.hash(&mut hasher);
+    let key = format!("{:016x}", hasher.finish());
+    Some(cache_path()?.join("status").join(key))
+}
+
+fn hash_stat(path: &Path, hasher: &mut DefaultHasher) {
+    match fs::symlink_metadata(path) {
+        Ok(m) => (
+            m.mtime(),
+            m.mtime_nsec(),
+            m.ctime(),
+            m.size(),
+            m.ino(),
+            m.mode(),
+        )
+            .hash(hasher),
+        Err(_) => 0.hash(hasher),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn config() -> DirtyCheckConfig {
+        DirtyCheckConfig {
+            show_dirty_state: true,
+            show_untracked_files: true,
+            ignore_submodules: None,
+        }
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    fn is_current(repo: &Repository, snapshot: &Snapshot) -> bool {
+        let fingerprint = fingerprint(repo, &config(), true, &snapshot.untracked_dirs, deadline());
+        fingerprint == Some(snapshot.fingerprint)
+    }
+
+    #[test]
+    fn test_untracked_dirs() -> std::io::Result<()> {
+        let dir = TempDir::new()?;
+        let repo = gix::init(dir.path()).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n")?;
+        fs::create_dir_all(dir.path().join("empty/nested"))?;
+        fs::create_dir(dir.path().join("target"))?;
+
+        let past = Instant::now() - Duration::from_secs(1);
+        assert!(snapshot(&repo, &config(), true, past).is_none());
+        let snapshot = snapshot(&repo, &config(), true, deadline()).unwrap();
+        assert_eq!(
+            snapshot.untracked_dirs,
+            [PathBuf::from("empty"), PathBuf::from("empty/nested")]
+        );
+        assert!(is_current(&repo, &snapshot));
+
+        fs::write(dir.path().join("target/build.o"), "")?;
+        assert!(is_current(&repo, &snapshot));
+
+        fs::write(dir.path().join("empty/nested/new"), "")?;
+        assert!(!is_current(&repo, &snapshot));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use crate::utils::output_within;
+use gix::Repository;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A watchman hook answers in tens of milliseconds once its watch is set up
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FsMonitor {
+    Daemon(PathBuf),
+    Hook {
+        command: OsString,
+        version: Option<u8>,
+    },
+}
+
+pub struct FsMonitorAnswer {
+    pub token: String,
+    pub has_changes: bool,
+}
+
+pub fn configured_fsmonitor(repo: &Repository) -> Option<FsMonitor> {
+    repo.workdir()?;
+    let config = repo.config_snapshot();
+
+    match config.boolean("core.fsmonitor") {
+        Some(true) => {
+            return Some(FsMonitor::Daemon(
+                repo.git_dir().join("fsmonitor--daemon.ipc"),
+            ));
+        }
+        Some(false) => return None,
+        None => {}
+    }
+
+    // Like git, only run a hook that comes from a config file we trust
+    let command = config.trusted_program("core.fsmonitor")?.into_owned();
+    if command.is_empty() {
+        return None;
+    }
+
+    let version = config
+        .integer("core.fsmonitorHookVersion")
+        .and_then(|v| u8::try_from(v).ok())
+        .filter(|v| [1, 2].contains(v));
+    Some(FsMonitor::Hook { command, version })
+}
+
+// A token to ask about later changes with, taken before looking at the workdir, so that
+// nothing that happens during the walk is missed
+pub fn current_token(repo: &Repository, monitor: &FsMonitor) -> Option<String> {
+    let token = match monitor {
+        FsMonitor::Daemon(_) => "builtin:fake".to_string(),
+        FsMonitor::Hook { .. } => now_nanos().to_string(),
+    };
+    changes_since(repo, monitor, &token).map(|a| a.token)
+}
+
+pub fn changes_since(
+    repo: &Repository,
+    monitor: &FsMonitor,
+    token: &str,
+) -> Option<FsMonitorAnswer> {
+    match monitor {
+        FsMonitor::Daemon(socket) => query_builtin_daemon(socket, token),
+        FsMonitor::Hook { command, version } => {
+            // Without a configured version git tries 2 first and falls back to 1
+            if *version != Some(1)
+                && let Some(answer) = run_hook_v2(repo, command, token)
+            {
+                return Some(answer);
+            }
+            if *version == Some(2) {
+                return None;
+            }
+            run_hook_v1(repo, command, token)
+        }
+    }
+}
+
+fn run_hook_v2(repo: &Repository, command: &OsString, token: &str) -> Option<FsMonitorAnswer> {
+    let output = run_hook(repo, command, "2", token)?;
+    let mut fields = output.split(|&b| b == 0);
+    let token = String::from_utf8(fields.next()?.to_vec()).ok()?;
+    if token.is_empty() {
+        return None;
+    }
+
+    Some(FsMonitorAnswer {
+        token,
+        has_changes: has_workdir_changes(fields),
+    })
+}
+
+fn run_hook_v1(repo: &Repository, command: &OsString, token: &str) -> Option<FsMonitorAnswer> {
+    // Version 1 hooks take a timestamp and don't hand out tokens of their own
+    token.parse::<u128>().ok()?;
+    let now = now_nanos();
+    let output = run_hook(repo, command, "1", token)?;
+
+    Some(FsMonitorAnswer {
+        token: now.to_string(),
+        has_changes: has_workdir_changes(output.split(|&b| b == 0)),
+    })
+}
+
+fn run_hook(repo: &Repository, hook: &OsString, version: &str, token: &str) -> Option<Vec<u8>> {
+    // Git runs the hook through the shell from the top of the workdir
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", hook.to_str()?))
+        .arg(hook)
+        .args([version, token])
+        .current_dir(repo.workdir()?);
+    output_within(&mut command, QUERY_TIMEOUT)
+}
+
+// The built-in daemon speaks git's simple IPC, which is pkt-lines ended by a flush packet
+fn query_builtin_daemon(socket: &PathBuf, token: &str) -> Option<FsMonitorAnswer> {
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT)).ok()?;
+
+    let mut request = format!("{:04x}", token.len() + 4).into_bytes();
+    request.extend_from_slice(token.as_bytes());
+    request.extend_from_slice(b"0000");
+    stream.write_all(&request).ok()?;
+
+    let mut response = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).ok()?;
+        let len = usize::from_str_radix(std::str::from_utf8(&len).ok()?, 16).ok()?;
+        if len == 0 {
+            break;
+        }
+        let mut data = vec![0u8; len.checked_sub(4)?];
+        stream.read_exact(&mut data).ok()?;
+        response.extend_from_slice(&data);
+    }
+
+    let mut fields = response.split(|&b| b == 0);
+    let token = String::from_utf8(fields.next()?.to_vec()).ok()?;
+    if token.is_empty() {
+        return None;
+    }
+
+    Some(FsMonitorAnswer {
+        token,
+        has_changes: has_workdir_changes(fields),
+    })
+}
+
+// Paths inside the git dir are already covered by the cache fingerprint, and "/" stands
+// for everything when the monitor can't tell
+fn has_workdir_changes<'a>(paths: impl Iterator<Item = &'a [u8]>) -> bool {
+    paths
+        .filter(|p| !p.is_empty())
+        .any(|p| p != b".git" && !p.starts_with(b".git/"))
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
@@ -0,0 +1,365 @@
+use crate::repo::global_excludes_file;
+use gix::Repository;
+use gix::objs::Kind;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Set when git lists an untracked directory only if it has something in it
+const DIR_HIDE_EMPTY_DIRECTORIES: u32 = 1 << 2;
+
+// git's untracked cache remembers every directory it listed along with its stat data and the
+// untracked files it found there. As long as none of those directories changed, that answers
+// the question without listing anything. gix decodes the extension but keeps it private, so
+// it's read straight from the index file. None means the cache can't vouch for the workdir.
+pub fn cached_has_untracked_files(repo: &Repository) -> Option<bool> {
+    if repo.config_snapshot().boolean("core.untrackedCache") == Some(false) {
+        return None;
+    }
+
+    let index_path = repo.index_path();
+    let index = fs::read(&index_path).ok()?;
+    let hash_len = repo.object_hash().len_in_bytes();
+    let cache = parse_untracked_cache(find_extension(&index, b"UNTR", hash_len)?, hash_len)?;
+
+    // A cache written for another location is one git would throw away too
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let location = format!("Location {}, system ", workdir.to_str()?);
+    if !cache.ident.starts_with(location.as_bytes()) {
+        return None;
+    }
+
+    let info_exclude = repo.common_dir().join("info").join("exclude");
+    if !cache
+        .info_exclude
+        .matches(fs::metadata(info_exclude).ok().as_ref())
+        || !cache.excludes_file.matches(
+            global_excludes_file(repo)
+                .and_then(|p| fs::metadata(p).ok())
+                .as_ref(),
+        )
+    {
+        return None;
+    }
+
+    // Directories changed within the same tick as the index write can't be told apart
+    let index_mtime = Stat::from(&fs::metadata(&index_path).ok()?).mtime;
+    let mut has_untracked = false;
+
+    for dir in &cache.directories {
+        let path = workdir.join(&dir.path);
+        let stat = Stat::from(&fs::symlink_metadata(&path).ok()?);
+        if dir.stat.as_ref() != Some(&stat) || stat.mtime >= index_mtime {
+            return None;
+        }
+
+        if let Some(id) = &dir.exclude_file_id {
+            let contents = fs::read(path.join(&cache.exclude_per_dir)).ok()?;
+            if !is_exclude_file_id(repo, contents, id) {
+                return None;
+            }
+        }
+
+        for entry in &dir.untracked {
+            // Without hiding empty directories, one listed here may well be empty
+            if entry.ends_with(b"/") && cache.dir_flags & DIR_HIDE_EMPTY_DIRECTORIES == 0 {
+                return None;
+            }
+            has_untracked = true;
+        }
+    }
+
+    Some(has_untracked)
+}
+
+// git takes the id from the index when the file is tracked and unchanged, and otherwise
+// hashes what it parsed, which has a newline added to the end
+fn is_exclude_file_id(repo: &Repository, mut contents: Vec<u8>, id: &[u8]) -> bool {
+    let hash = |data: &[u8]| gix::objs::compute_hash(repo.object_hash(), Kind::Blob, data).ok();
+    if hash(&contents).is_some_and(|h| h.as_bytes() == id) {
+        return true;
+    }
+    if contents.is_empty() {
+        return false;
+    }
+    contents.push(b'\n');
+    hash(&contents).is_some_and(|h| h.as_bytes() == id)
+}
+
+// The parts of stat data that git compares, truncated to 32 bits like it stores them.
+// Nanoseconds only count when git was built to record them.
+#[derive(Clone, Debug)]
+struct Stat {
+    ctime: (u32, u32),
+    mtime: (u32, u32),
+    ino: u32,
+    size: u32,
+}
+
+impl From<&Metadata> for Stat {
+    fn from(m: &Metadata) -> Self {
+        Stat {
+            ctime: (m.ctime() as u32, m.ctime_nsec() as u32),
+            mtime: (m.mtime() as u32, m.mtime_nsec() as u32),
+            ino: m.ino() as u32,
+            size: m.size() as u32,
+        }
+    }
+}
+
+impl PartialEq for Stat {
+    fn eq(&self, other: &Self) -> bool {
+        let time_eq = |a: (u32, u32), b: (u32, u32)| a.0 == b.0 && (a.1 == 0 || a.1 == b.1);
+        time_eq(self.ctime, other.ctime)
+            && time_eq(self.mtime, other.mtime)
+            && self.ino == other.ino
+            && self.size == other.size
+    }
+}
+
+impl Stat {
+    // git zeroes only the times of a missing file, and leaves the rest as it was
+    fn matches(&self, metadata: Option<&Metadata>) -> bool {
+        match metadata {
+            Some(m) => *self == Stat::from(m),
+            None => self.ctime == (0, 0) && self.mtime == (0, 0),
+        }
+    }
+}
+
+struct Directory<'a> {
+    path: PathBuf,
+    untracked: Vec<&'a [u8]>,
+    stat: Option<Stat>,
+    exclude_file_id: Option<Vec<u8>>,
+}
+
+struct UntrackedCache<'a> {
+    ident: &'a [u8],
+    info_exclude: Stat,
+    excludes_file: Stat,
+    dir_flags: u32,
+    exclude_per_dir: PathBuf,
+    directories: Vec<Directory<'a>>,
+}
+
+// The extensions follow the entries, which have to be stepped over one by one
+fn find_extension<'a>(index: &'a [u8], signature: &[u8; 4], hash_len: usize) -> Option<&'a [u8]> {
+    if index.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = read_u32(index, 4)?;
+    let count = read_u32(index, 8)?;
+    let data = index.get(..index.len().checked_sub(hash_len)?)?;
+
+    let mut at = 12;
+    for _ in 0..count {
+        at = skip_entry(data, at, version, hash_len)?;
+    }
+
+    while at + 8 <= data.len() {
+        let size = read_u32(data, at + 4)? as usize;
+        let extension = data.get(at + 8..at + 8 + size)?;
+        if data[at..at + 4] == *signature {
+            return Some(extension);
+        }
+        at += 8 + size;
+    }
+
+    None
+}
+
+// Stat data, an object id and flags, then the path. Until version 4 the path is padded with
+// NULs to a multiple of eight bytes, and from then on it's prefix compressed instead.
+fn skip_entry(data: &[u8], start: usize, version: u32, hash_len: usize) -> Option<usize> {
+    let flags_at = start + 40 + hash_len;
+    let flags = u16::from_be_bytes(data.get(flags_at..flags_at + 2)?.try_into().ok()?);
+    let mut at = flags_at + 2;
+    if flags & 0x4000 != 0 {
+        at += 2;
+    }
+    if version >= 4 {
+        at += var_int(data.get(at..)?)?.1;
+    }
+
+    let path_end = at + data.get(at..)?.iter().position(|&b| b == 0)?;
+    Some(if version >= 4 {
+        path_end + 1
+    } else {
+        start + ((path_end - start + 8) & !7)
+    })
+}
+
+fn parse_untracked_cache(data: &[u8], hash_len: usize) -> Option<UntrackedCache<'_>> {
+    let mut r = Reader { data };
+    let ident_len = r.var_int()?;
+    let ident = r.take(usize::try_from(ident_len).ok()?)?;
+    let info_exclude = r.stat()?;
+    r.take(hash_len)?;
+    let excludes_file = r.stat()?;
+    r.take(hash_len)?;
+    let dir_flags = r.u32()?;
+    let exclude_per_dir = PathBuf::from(std::str::from_utf8(r.c_str()?).ok()?);
+
+    let count = usize::try_from(r.var_int()?).ok()?;
+    if count == 0 {
+        return None;
+    }
+    let mut directories = Vec::with_capacity(count);
+    parse_directory(&mut r, Path::new(""), &mut directories)?;
+    if directories.len() != count {
+        return None;
+    }
+
+    let valid = r.ewah()?;
+    let _check_only = r.ewah()?;
+    let hash_valid = r.ewah()?;
+    for i in valid {
+        directories.get_mut(i)?.stat = Some(r.stat()?);
+    }
+    for i in hash_valid {
+        directories.get_mut(i)?.exclude_file_id = Some(r.take(hash_len)?.to_vec());
+    }
+
+    Some(UntrackedCache {
+        ident,
+        info_exclude,
+        excludes_file,
+        dir_flags,
+        exclude_per_dir,
+        directories,
+    })
+}
+
+// Each block is a directory followed by the blocks of its subdirectories
+fn parse_directory<'a>(
+    r: &mut Reader<'a>,
+    parent: &Path,
+    directories: &mut Vec<Directory<'a>>,
+) -> Option<()> {
+    let untracked_count = r.var_int()?;
+    let dir_count = r.var_int()?;
+    let path = parent.join(std::str::from_utf8(r.c_str()?).ok()?);
+
+    let mut untracked = Vec::new();
+    for _ in 0..untracked_count {
+        untracked.push(r.c_str()?);
+    }
+
+    directories.push(Directory {
+        path: path.clone(),
+        untracked,
+        stat: None,
+        exclude_file_id: None,
+    });
+    for _ in 0..dir_count {
+        parse_directory(r, &path, directories)?;
+    }
+    Some(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.data.split_at_checked(len)?;
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn var_int(&mut self) -> Option<u64> {
+        let (value, len) = var_int(self.data)?;
+        self.take(len)?;
+        Some(value)
+    }
+
+    fn c_str(&mut self) -> Option<&'a [u8]> {
+        let len = self.data.iter().position(|&b| b == 0)?;
+        let s = self.take(len)?;
+        self.take(1)?;
+        Some(s)
+    }
+
+    fn stat(&mut self) -> Option<Stat> {
+        let ctime = (self.u32()?, self.u32()?);
+        let mtime = (self.u32()?, self.u32()?);
+        let _dev = self.u32()?;
+        let ino = self.u32()?;
+        let _uid = self.u32()?;
+        let _gid = self.u32()?;
+        let size = self.u32()?;
+        Some(Stat {
+            ctime,
+            mtime,
+            ino,
+            size,
+        })
+    }
+
+    // An EWAH compressed bitmap: runs of all-zero or all-one words, each run followed by
+    // literal words, behind a word count and trailed by the position of the last run
+    fn ewah(&mut self) -> Option<Vec<usize>> {
+        let _bit_count = self.u32()?;
+        let word_count = self.u32()?;
+        let mut words = Vec::with_capacity(word_count as usize);
+        for _ in 0..word_count {
+            words.push(u64::from_be_bytes(self.take(8)?.try_into().ok()?));
+        }
+        self.u32()?;
+
+        let mut bits = Vec::new();
+        let mut position = 0;
+        let mut words = words.into_iter();
+        while let Some(marker) = words.next() {
+            let run_len = ((marker >> 1) & 0xffff_ffff) as usize * 64;
+            if marker & 1 == 1 {
+                bits.extend(position..position + run_len);
+            }
+            position += run_len;
+
+            for _ in 0..(marker >> 33) {
+                let word = words.next()?;
+                bits.extend(
+                    (0..64)
+                        .filter(|b| word & (1 << b) != 0)
+                        .map(|b| position + b),
+                );
+                position += 64;
+            }
+        }
+        Some(bits)
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+// git's offset encoding, where every continuation also adds one
+fn var_int(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().enumerate() {
+        if i > 0 {
+            value = value.checked_add(1)?.checked_mul(128)?;
+        }
+        value |= u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[test]
+fn test_var_int() {
+    assert_eq!(var_int(&[0x05]), Some((5, 1)));
+    assert_eq!(var_int(&[0x80, 0x00]), Some((128, 2)));
+    assert_eq!(var_int(&[0x81, 0x7f, 0xff]), Some((383, 2)));
+    assert_eq!(var_int(&[0x80]), None);
+}
@@ -1,7 +1,11 @@
 pub mod env;
 pub mod path;
+pub mod process;
+pub mod time;
 pub mod version;
 
 pub use env::*;
 pub use path::*;
+pub use process::*;
+pub use time::*;
 pub use version::*;
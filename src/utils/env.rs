@@ -17,6 +17,22 @@ pub fn env_home() -> Option<String> {
     env::var("HOME").ok()
 }
 
+pub fn env_git_dir() -> Option<String> {
+    env::var("GIT_DIR").ok().filter(|s| !s.is_empty())
+}
+
+pub fn env_git_work_tree() -> Option<String> {
+    env::var("GIT_WORK_TREE").ok().filter(|s| !s.is_empty())
+}
+
+// Accepts the same spellings as a boolean in git config
+pub fn env_git_discovery_across_filesystem() -> bool {
+    env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM").is_ok_and(|v| {
+        let v = v.trim().to_ascii_lowercase();
+        matches!(v.as_str(), "true" | "yes" | "on") || v.parse::<i64>().is_ok_and(|n| n != 0)
+    })
+}
+
 pub fn supports_truecolor() -> bool {
     env::var("COLORTERM")
         .is_ok_and(|v| v.eq_ignore_ascii_case("truecolor") || v.eq_ignore_ascii_case("24bit"))
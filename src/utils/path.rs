@@ -1,19 +1,31 @@
-use nix::NixPath;
 use std::env;
 use std::path::{Component, Path, PathBuf};
 
-pub fn displayed_path(repo_workdir: Option<&Path>) -> Option<String> {
+pub fn displayed_path(repo_workdir: Option<&Path>, superproject: Option<&Path>) -> Option<String> {
     let path = current_path()?;
     let home = home_path()?;
-    short_path(&path, &home, repo_workdir)
+    short_path(&path, &home, repo_workdir, superproject)
 }
 
-fn short_path(path: &Path, home: &Path, repo_workdir: Option<&Path>) -> Option<String> {
+fn short_path(
+    path: &Path,
+    home: &Path,
+    repo_workdir: Option<&Path>,
+    superproject: Option<&Path>,
+) -> Option<String> {
+    // A dotfiles repo in home keeps the familiar ~ prefix
     if let Some(workdir) = repo_workdir
+        && workdir != home
         && let Some(workdir_parent) = workdir.parent()
         && path.starts_with(workdir)
         && let Ok(rel) = path.strip_prefix(workdir_parent)
     {
+        // Inside a submodule, name the superproject so it's not mistaken for a standalone repo
+        if let Some(parent_name) = superproject.and_then(|p| p.file_name()) {
+            let parent_name = parent_name.to_string_lossy();
+            return Some(format!("{parent_name} ▸ {}", shorten_path(rel, None)));
+        }
+
         return Some(shorten_path(rel, Some("…")));
     }
 
@@ -25,7 +37,7 @@ fn short_path(path: &Path, home: &Path, repo_workdir: Option<&Path>) -> Option<S
 }
 
 fn shorten_path(path: &Path, prefix: Option<&str>) -> String {
-    if path.is_empty() {
+    if path.as_os_str().is_empty() {
         return prefix.unwrap_or("?").into();
     }
 
@@ -61,6 +73,21 @@ pub fn home_path() -> Option<PathBuf> {
     env::var_os("HOME").map(PathBuf::from)
 }
 
+pub fn cache_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| Some(home_path()?.join(".cache")))?;
+    Some(base.join(env!("CARGO_PKG_NAME")))
+}
+
+pub fn runtime_path() -> Option<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(p) if p.is_absolute() => Some(p.join(env!("CARGO_PKG_NAME"))),
+        _ => cache_path(),
+    }
+}
+
 pub fn current_path() -> Option<PathBuf> {
     // env::current_dir follows symlinks, so try to use PWD if it seems valid
     let cwd = env::current_dir().ok()?;
@@ -81,25 +108,54 @@ pub fn current_path() -> Option<PathBuf> {
 #[test]
 fn test_short_path_inner() {
     assert_eq!(
-        short_path(&PathBuf::from("/"), &PathBuf::from("/home"), None),
+        short_path(&PathBuf::from("/"), &PathBuf::from("/home"), None, None),
         Some("/".into())
     );
     assert_eq!(
-        short_path(&PathBuf::from("/a/b/c/d"), &PathBuf::from("/home"), None),
+        short_path(
+            &PathBuf::from("/a/b/c/d"),
+            &PathBuf::from("/home"),
+            None,
+            None
+        ),
         Some("…/b/c/d".into())
     );
     assert_eq!(
-        short_path(&PathBuf::from("/home/a"), &PathBuf::from("/home"), None),
+        short_path(
+            &PathBuf::from("/home/a"),
+            &PathBuf::from("/home"),
+            None,
+            None
+        ),
         Some("~/a".into())
     );
     assert_eq!(
         short_path(
             &PathBuf::from("/home/repo"),
             &PathBuf::from("/home"),
-            Some(&PathBuf::from("/home/repo"))
+            Some(&PathBuf::from("/home/repo")),
+            None
         ),
         Some("…/repo".into())
     );
+    assert_eq!(
+        short_path(
+            &PathBuf::from("/home/repo/libs/sub/src"),
+            &PathBuf::from("/home"),
+            Some(&PathBuf::from("/home/repo/libs/sub")),
+            Some(&PathBuf::from("/home/repo"))
+        ),
+        Some("repo ▸ sub/src".into())
+    );
+    assert_eq!(
+        short_path(
+            &PathBuf::from("/home/a"),
+            &PathBuf::from("/home"),
+            Some(&PathBuf::from("/home")),
+            None
+        ),
+        Some("~/a".into())
+    );
 }
 
 #[test]
@@ -0,0 +1,35 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Stdout of a command that succeeds in time, anything slower is killed so the prompt stays snappy
+pub fn output_within(command: &mut Command, timeout: Duration) -> Option<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Read on the side, so that a long output can't fill the pipe and stall the command
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    loop {
+        match child.try_wait().ok()? {
+            Some(status) if status.success() => return reader.join().ok()?.ok(),
+            Some(_) => return None,
+            None if start.elapsed() > timeout => {
+                _ = child.kill();
+                _ = child.wait();
+                return None;
+            }
+            None => thread::sleep(Duration::from_millis(2)),
+        }
+    }
+}
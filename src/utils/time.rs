@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+// The largest whole unit, which is all a prompt has room for
+pub fn short_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3_600 => format!("{}m", secs / 60),
+        3_600..86_400 => format!("{}h", secs / 3_600),
+        86_400..31_536_000 => format!("{}d", secs / 86_400),
+        _ => format!("{}y", secs / 31_536_000),
+    }
+}
+
+#[test]
+fn test_short_age() {
+    assert_eq!(short_age(Duration::from_secs(0)), "0s");
+    assert_eq!(short_age(Duration::from_secs(59)), "59s");
+    assert_eq!(short_age(Duration::from_secs(60)), "1m");
+    assert_eq!(short_age(Duration::from_secs(2 * 3_600 + 59)), "2h");
+    assert_eq!(short_age(Duration::from_secs(3 * 86_400)), "3d");
+    assert_eq!(short_age(Duration::from_secs(400 * 86_400)), "1y");
+}